@@ -0,0 +1,308 @@
+use crate::{config::SiteConfig, page::Page};
+use anyhow::Result;
+use chrono::Local;
+use glob::glob;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+const DEFAULT_POST_ARCHETYPE: &str = "#+TITLE: {{ title }}
+#+DATE: <{{ date }}>
+#+DESCRIPTION:
+#+TAGS:
+
+";
+
+const DEFAULT_PAGE_ARCHETYPE: &str = "#+TITLE: {{ title }}
+
+";
+
+// Lowercase ascii words joined by dashes
+pub fn slugify(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-")
+}
+
+// Today in the format Page::parse expects for dates
+fn today() -> String {
+    Local::now().format("%Y-%m-%d %a").to_string()
+}
+
+// Title from the last part of a path such as "about/contact-me"
+fn title_from_path(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path).replace('-', " ");
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => name,
+    }
+}
+
+// Archetypes in the site take precedence over the ones in the theme
+fn find_archetype(root_path: &Path, site_config: &SiteConfig, kind: &str) -> Option<PathBuf> {
+    let file_name = format!("{}.org", kind);
+
+    let mut paths = vec![root_path.join("archetypes").join(&file_name)];
+
+    if let Some(theme) = &site_config.site.theme {
+        paths.push(
+            root_path
+                .join("themes")
+                .join(theme)
+                .join("archetypes")
+                .join(&file_name),
+        );
+    }
+
+    paths.into_iter().find(|path| path.is_file())
+}
+
+// Content dirs with an _index.org of the posts type
+fn posts_sections(root_path: &Path, site_config: &SiteConfig) -> Result<Vec<PathBuf>> {
+    let content_path = root_path.join("content");
+    let mut sections = Vec::new();
+
+    for index_path in
+        glob(&format!("{}/**/_index.org", content_path.to_string_lossy()))?.filter_map(|p| p.ok())
+    {
+        let page = Page::read_file(root_path, index_path.clone(), site_config, None)?;
+
+        if page.ty() == Some("posts") {
+            if let Some(section_path) = index_path.parent() {
+                sections.push(section_path.into());
+            }
+        }
+    }
+
+    sections.sort();
+
+    Ok(sections)
+}
+
+fn render_archetype(
+    root_path: &Path,
+    site_config: &SiteConfig,
+    kind: &str,
+    default: &str,
+    context: &tera::Context,
+) -> Result<String> {
+    let archetype = match find_archetype(root_path, site_config, kind) {
+        Some(path) => fs::read_to_string(path)?,
+        None => default.into(),
+    };
+
+    Ok(tera::Tera::one_off(&archetype, context, false)?)
+}
+
+fn write_new(path: &Path, contents: &str) -> Result<()> {
+    if path.exists() {
+        return Err(ArchetypeError::AlreadyExists(path.into()).into());
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(path, contents)?;
+
+    Ok(())
+}
+
+// Creates a post in the posts section, or the given section when there are several
+pub fn new_post(
+    root_path: &Path,
+    site_config: &SiteConfig,
+    title: &str,
+    section: Option<&str>,
+) -> Result<PathBuf> {
+    let sections = posts_sections(root_path, site_config)?;
+    let content_path = root_path.join("content");
+
+    let section_path = match section {
+        Some(section) => {
+            let section_path = content_path.join(section.trim_matches('/'));
+
+            if !sections.contains(&section_path) {
+                return Err(ArchetypeError::NotAPostsSection(section.into()).into());
+            }
+
+            section_path
+        }
+        None => match sections.as_slice() {
+            [section_path] => section_path.clone(),
+            [] => return Err(ArchetypeError::MissingPostsSection.into()),
+            _ => return Err(ArchetypeError::AmbiguousPostsSection.into()),
+        },
+    };
+
+    let slug = slugify(title);
+
+    if slug.is_empty() {
+        return Err(ArchetypeError::EmptySlug(title.into()).into());
+    }
+
+    let mut context = tera::Context::new();
+    context.insert("title", title);
+    context.insert("slug", &slug);
+    context.insert("date", &today());
+
+    let contents = render_archetype(
+        root_path,
+        site_config,
+        "post",
+        DEFAULT_POST_ARCHETYPE,
+        &context,
+    )?;
+
+    let path = section_path.join(format!("{}.org", slug));
+    write_new(&path, &contents)?;
+
+    Ok(path)
+}
+
+// Creates a page at a path relative to the content dir, such as "about" or "projects/nuko"
+pub fn new_page(root_path: &Path, site_config: &SiteConfig, page_path: &str) -> Result<PathBuf> {
+    let parts: Vec<String> = page_path
+        .trim_end_matches(".org")
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .map(slugify)
+        .collect();
+
+    if parts.is_empty() || parts.iter().any(|part| part.is_empty()) {
+        return Err(ArchetypeError::EmptySlug(page_path.into()).into());
+    }
+
+    let slug = parts.join("/");
+
+    let mut context = tera::Context::new();
+    context.insert("title", &title_from_path(&slug));
+    context.insert("slug", &slug);
+    context.insert("date", &today());
+
+    let contents = render_archetype(
+        root_path,
+        site_config,
+        "page",
+        DEFAULT_PAGE_ARCHETYPE,
+        &context,
+    )?;
+
+    let path = root_path.join("content").join(format!("{}.org", slug));
+    write_new(&path, &contents)?;
+
+    Ok(path)
+}
+
+#[derive(Error, Debug)]
+pub enum ArchetypeError {
+    #[error("\"{0:?}\" already exists")]
+    AlreadyExists(PathBuf),
+    #[error("no section with \"#+TYPE: posts\" in its _index.org")]
+    MissingPostsSection,
+    #[error("there are several posts sections, choose one with --section")]
+    AmbiguousPostsSection,
+    #[error("\"{0}\" is not a posts section")]
+    NotAPostsSection(String),
+    #[error("cannot create a file name from \"{0}\"")]
+    EmptySlug(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn site(files: &[(&str, &str)]) -> (tempfile::TempDir, SiteConfig) {
+        let root = tempfile::tempdir().unwrap();
+
+        for (path, text) in files {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+
+        let config =
+            SiteConfig::parse("[site]\nbase_url = \"https://example.com\"\ntheme = \"test\"\n")
+                .unwrap();
+
+        (root, config)
+    }
+
+    #[test]
+    fn slugs_and_titles() {
+        assert_eq!(slugify("Hello, World! 2"), "hello-world-2");
+        assert_eq!(slugify("¡¿?!"), "");
+        assert_eq!(title_from_path("about/contact-me"), "Contact me");
+    }
+
+    #[test]
+    fn new_post_from_default_archetype() {
+        let (root, config) = site(&[("content/posts/_index.org", "#+TYPE: posts\n")]);
+
+        let path = new_post(root.path(), &config, "First Post", None).unwrap();
+        assert_eq!(path, root.path().join("content/posts/first-post.org"));
+
+        // The post can be read as is, including its date
+        let page = Page::read_file(root.path(), path, &config, None).unwrap();
+        assert_eq!(page.title(), Some("First Post"));
+        assert!(page.date().is_some());
+
+        let err = new_post(root.path(), &config, "First post", None).unwrap_err();
+        assert!(err.to_string().ends_with("already exists"), "{}", err);
+        assert!(new_post(root.path(), &config, "?", None).is_err());
+    }
+
+    #[test]
+    fn new_post_sections() {
+        let (root, config) = site(&[("content/about.org", "#+TITLE: About\n")]);
+        let err = new_post(root.path(), &config, "A", None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            ArchetypeError::MissingPostsSection.to_string()
+        );
+
+        let (root, config) = site(&[
+            ("content/posts/_index.org", "#+TYPE: posts\n"),
+            ("content/notes/_index.org", "#+TYPE: posts\n"),
+            ("content/docs/_index.org", "#+TITLE: Docs\n"),
+        ]);
+        let err = new_post(root.path(), &config, "A", None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            ArchetypeError::AmbiguousPostsSection.to_string()
+        );
+
+        let path = new_post(root.path(), &config, "A", Some("/notes/")).unwrap();
+        assert_eq!(path, root.path().join("content/notes/a.org"));
+        assert!(new_post(root.path(), &config, "A", Some("docs")).is_err());
+    }
+
+    #[test]
+    fn site_archetypes_before_theme_archetypes() {
+        let (root, config) = site(&[
+            ("themes/test/archetypes/page.org", "theme {{ title }}\n"),
+            ("themes/test/archetypes/post.org", "theme {{ slug }}\n"),
+            ("archetypes/page.org", "site {{ slug }}\n"),
+            ("content/posts/_index.org", "#+TYPE: posts\n"),
+        ]);
+
+        let page = new_page(root.path(), &config, "Projects/Nuko Site.org").unwrap();
+        assert_eq!(page, root.path().join("content/projects/nuko-site.org"));
+        assert_eq!(
+            fs::read_to_string(page).unwrap(),
+            "site projects/nuko-site\n"
+        );
+
+        let post = new_post(root.path(), &config, "A post", None).unwrap();
+        assert_eq!(fs::read_to_string(post).unwrap(), "theme a-post\n");
+
+        assert!(new_page(root.path(), &config, "/").is_err());
+        assert!(new_page(root.path(), &config, "a/?/b").is_err());
+    }
+}
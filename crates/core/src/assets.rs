@@ -0,0 +1,201 @@
+use crate::config::SiteAssetsConfig;
+use anyhow::Result;
+use glob::glob;
+use serde_derive::Serialize;
+use sha2::{Digest, Sha256, Sha384};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+const FINGERPRINT_LENGTH: usize = 8;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AssetEntry {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<String>,
+    // Sha256 of the content in hex, for get_url(hash=true)
+    #[serde(skip)]
+    pub hash: String,
+}
+
+// Maps asset paths relative to the out dir to their fingerprinted paths
+#[derive(Default, Debug, Serialize)]
+pub struct AssetManifest {
+    assets: BTreeMap<String, AssetEntry>,
+}
+
+// Inserts the hash before the extension, "style.css" becomes "style.3f2a1b4c.css"
+fn fingerprinted_path(path: &Path, hash: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, hash, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, hash),
+    };
+
+    path.with_file_name(file_name)
+}
+
+fn is_asset(path: &Path, extensions: &[String]) -> bool {
+    path.extension().is_some_and(|extension| {
+        extensions
+            .iter()
+            .any(|asset_extension| extension.eq_ignore_ascii_case(asset_extension.as_str()))
+    })
+}
+
+impl AssetManifest {
+    // Builds the manifest for the files in the out dir with an asset extension,
+    // they are hashed even when they are neither fingerprinted nor given an integrity
+    pub fn build(out_path: &Path, config: &SiteAssetsConfig) -> Result<AssetManifest> {
+        let mut manifest = AssetManifest::default();
+
+        let files: Vec<PathBuf> = glob(&format!("{}/**/*", out_path.to_string_lossy()))?
+            .filter_map(|p| p.ok())
+            .filter(|p| p.is_file() && is_asset(p, &config.extensions))
+            .collect();
+
+        for file in files {
+            let content = fs::read(&file)?;
+            let rel_path = file.strip_prefix(out_path)?;
+            let hash = format!("{:x}", Sha256::digest(&content));
+
+            let path = if config.fingerprint {
+                let fingerprinted = fingerprinted_path(rel_path, &hash[..FINGERPRINT_LENGTH]);
+
+                fs::copy(&file, out_path.join(&fingerprinted))?;
+
+                fingerprinted
+            } else {
+                rel_path.into()
+            };
+
+            let integrity = if config.integrity {
+                Some(format!(
+                    "sha384-{}",
+                    base64::encode(Sha384::digest(&content))
+                ))
+            } else {
+                None
+            };
+
+            manifest.assets.insert(
+                rel_path.to_string_lossy().into(),
+                AssetEntry {
+                    path: path.to_string_lossy().into(),
+                    integrity,
+                    hash,
+                },
+            );
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn get(&self, path: &str) -> Option<&AssetEntry> {
+        self.assets.get(path.trim_start_matches('/'))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(&self.assets)?)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn out_dir() -> tempfile::TempDir {
+        let out = tempfile::tempdir().unwrap();
+        fs::create_dir_all(out.path().join("static/css")).unwrap();
+        fs::write(out.path().join("static/css/style.css"), "body{}").unwrap();
+        fs::write(out.path().join("static/notes.txt"), "notes").unwrap();
+
+        out
+    }
+
+    fn config(fingerprint: bool, integrity: bool) -> SiteAssetsConfig {
+        SiteAssetsConfig {
+            fingerprint,
+            integrity,
+            ..SiteAssetsConfig::default()
+        }
+    }
+
+    #[test]
+    fn fingerprinted_path_keeps_extension() {
+        assert_eq!(
+            fingerprinted_path(Path::new("css/style.css"), "3f2a1b4c"),
+            Path::new("css/style.3f2a1b4c.css")
+        );
+        assert_eq!(
+            fingerprinted_path(Path::new("LICENSE"), "3f2a1b4c"),
+            Path::new("LICENSE.3f2a1b4c")
+        );
+    }
+
+    #[test]
+    fn manifest_fingerprints_assets() {
+        let out = out_dir();
+        let manifest = AssetManifest::build(out.path(), &config(true, false)).unwrap();
+        let hash = format!("{:x}", Sha256::digest(b"body{}"));
+
+        let entry = manifest.get("/static/css/style.css").unwrap();
+        assert_eq!(entry.path, format!("static/css/style.{}.css", &hash[..8]));
+        assert_eq!(entry.hash, hash);
+        assert_eq!(entry.integrity, None);
+        assert!(out.path().join(&entry.path).is_file());
+
+        // Only files with an asset extension are in the manifest
+        assert!(manifest.get("static/notes.txt").is_none());
+    }
+
+    #[test]
+    fn manifest_integrity() {
+        let out = out_dir();
+        let manifest = AssetManifest::build(out.path(), &config(false, true)).unwrap();
+
+        let entry = manifest.get("static/css/style.css").unwrap();
+        assert_eq!(entry.path, "static/css/style.css");
+        assert_eq!(
+            entry.integrity.as_deref(),
+            Some(&*format!(
+                "sha384-{}",
+                base64::encode(Sha384::digest(b"body{}"))
+            ))
+        );
+
+        manifest
+            .write(&out.path().join("asset-manifest.json"))
+            .unwrap();
+        let written: serde_json::Value =
+            serde_json::from_slice(&fs::read(out.path().join("asset-manifest.json")).unwrap())
+                .unwrap();
+        assert_eq!(
+            written["static/css/style.css"],
+            serde_json::json!({
+                "path": "static/css/style.css",
+                "integrity": entry.integrity,
+            })
+        );
+    }
+
+    #[test]
+    fn manifest_hashes_without_fingerprints() {
+        let out = out_dir();
+        let manifest = AssetManifest::build(out.path(), &config(false, false)).unwrap();
+
+        let entry = manifest.get("static/css/style.css").unwrap();
+        assert_eq!(entry.path, "static/css/style.css");
+        assert_eq!(entry.hash, format!("{:x}", Sha256::digest(b"body{}")));
+        assert_eq!(
+            fs::read_dir(out.path().join("static/css")).unwrap().count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,353 @@
+use anyhow::Result;
+use glob::glob;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+pub const CACHE_DIR: &str = ".nuko-cache";
+
+// Dirs of memoised results in the build cache, with the extension of their files
+const MEMO_KINDS: [(&str, &str); 3] = [
+    ("highlighted", "html"),
+    ("pages", "json"),
+    ("documents", "json"),
+];
+
+// Hash of every file in a folder, including the nuko version as the cached formats may change
+pub fn hash_folder(path: &Path) -> Result<String> {
+    let mut files: Vec<PathBuf> = glob(&format!("{}/**/*", path.to_string_lossy()))?
+        .filter_map(|p| p.ok())
+        .filter(|p| p.is_file())
+        .collect();
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    hasher.update(env!("CARGO_PKG_VERSION"));
+
+    for file in files {
+        hasher.update(file.strip_prefix(path)?.to_string_lossy().as_bytes());
+        hasher.update(fs::read(&file)?);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Hash of a serializable value, going through a json value so maps are hashed in key order
+pub fn hash_value<T: serde::Serialize>(value: &T) -> Result<String> {
+    let json = serde_json::to_string(&serde_json::to_value(value)?)?;
+
+    Ok(hash_parts(&[&json]))
+}
+
+pub fn hash_bytes(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+// Hash of the content of a file, a missing file hashes as empty
+pub fn hash_file(path: &Path) -> String {
+    fs::read(path)
+        .map(|content| hash_bytes(&content))
+        .unwrap_or_default()
+}
+
+pub fn hash_parts(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+
+    for part in parts {
+        // Length prefix so the parts cannot run into each other
+        hasher.update(part.len().to_string());
+        hasher.update(":");
+        hasher.update(part);
+    }
+
+    format!("{:x}", hasher.finalize())
+}
+
+// A file read while rendering an output, with the hash of its content at the time
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FileRead {
+    pub path: PathBuf,
+    pub hash: String,
+}
+
+// Files read since the last take, so cached outputs know which files they depend on
+#[derive(Default)]
+pub struct FileReads(Mutex<BTreeMap<PathBuf, String>>);
+
+impl FileReads {
+    pub fn insert(&self, path: &Path, hash: &str) {
+        self.0.lock().unwrap().insert(path.into(), hash.into());
+    }
+
+    pub fn take(&self) -> Vec<FileRead> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+            .into_iter()
+            .map(|(path, hash)| FileRead { path, hash })
+            .collect()
+    }
+}
+
+// An output of the previous build with the hash of its inputs
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CachedOutput {
+    pub hash: String,
+    // Other files in the out dir written while rendering the output, such as resized images
+    pub files: Vec<String>,
+    // Pages the templates looked up while rendering the output
+    #[serde(default)]
+    pub pages: Vec<String>,
+    // Data files, assets and images the templates read while rendering the output
+    #[serde(default)]
+    pub reads: Vec<FileRead>,
+}
+
+pub struct BuildCache {
+    path: PathBuf,
+    previous: BTreeMap<String, CachedOutput>,
+    outputs: Mutex<BTreeMap<String, CachedOutput>>,
+    // Memo files read or written by this build, the others are removed on save
+    used: Mutex<BTreeSet<PathBuf>>,
+    // Hashes of the files read by earlier builds, files do not change while rendering
+    file_hashes: Mutex<HashMap<PathBuf, String>>,
+}
+
+impl BuildCache {
+    pub fn load(root_path: &Path) -> Result<BuildCache> {
+        let path = root_path.join(CACHE_DIR).join("build");
+
+        // A broken manifest only means everything is rendered again
+        let previous = fs::read_to_string(path.join("outputs.json"))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+
+        Ok(BuildCache {
+            path,
+            previous,
+            outputs: Mutex::new(BTreeMap::new()),
+            used: Mutex::new(BTreeSet::new()),
+            file_hashes: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // The previous build of an output, which is only reused if its hash still matches
+    pub fn previous_output(&self, output: &str) -> Option<&CachedOutput> {
+        self.previous.get(output)
+    }
+
+    pub fn insert_output(&self, output: &str, cached_output: CachedOutput) {
+        self.outputs
+            .lock()
+            .unwrap()
+            .insert(output.into(), cached_output);
+    }
+
+    // Whether the files still have the content an earlier build read, each is hashed once
+    pub fn files_unchanged(&self, reads: &[FileRead]) -> bool {
+        let mut file_hashes = self.file_hashes.lock().unwrap();
+
+        reads.iter().all(|read| {
+            let hash = file_hashes
+                .entry(read.path.clone())
+                .or_insert_with(|| hash_file(&read.path));
+
+            *hash == read.hash
+        })
+    }
+
+    fn memo_file(&self, kind: &str, hash: &str) -> PathBuf {
+        let extension = MEMO_KINDS
+            .iter()
+            .find(|(memo_kind, _)| *memo_kind == kind)
+            .map_or("json", |(_, extension)| extension);
+        let file = self.path.join(kind).join(format!("{}.{}", hash, extension));
+
+        self.used.lock().unwrap().insert(file.clone());
+
+        file
+    }
+
+    // A value memoised by an earlier build, such as a parsed page
+    pub fn memoized<T: DeserializeOwned>(&self, kind: &str, hash: &str) -> Option<T> {
+        fs::read_to_string(self.memo_file(kind, hash))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+    }
+
+    pub fn memoize<T: serde::Serialize>(&self, kind: &str, hash: &str, value: &T) -> Result<()> {
+        let file = self.memo_file(kind, hash);
+
+        fs::create_dir_all(self.path.join(kind))?;
+        fs::write(file, serde_json::to_string(value)?)?;
+
+        Ok(())
+    }
+
+    // Memoises highlighted code by the hash of the highlighting settings and the code
+    pub fn highlighted<F>(&self, parts: &[&str], highlight: F) -> Result<String>
+    where
+        F: FnOnce() -> Result<String>,
+    {
+        let file = self.memo_file("highlighted", &hash_parts(parts));
+
+        if let Ok(html) = fs::read_to_string(&file) {
+            return Ok(html);
+        }
+
+        let html = highlight()?;
+
+        fs::create_dir_all(self.path.join("highlighted"))?;
+        fs::write(&file, &html)?;
+
+        Ok(html)
+    }
+
+    // Writes the outputs of this build for the next one
+    pub fn save(&self) -> Result<()> {
+        fs::create_dir_all(&self.path)?;
+        fs::write(
+            self.path.join("outputs.json"),
+            serde_json::to_string_pretty(&*self.outputs.lock().unwrap())?,
+        )?;
+
+        // Memo files the build did not use would only pile up
+        let used = self.used.lock().unwrap();
+
+        for (kind, _) in MEMO_KINDS.iter() {
+            let memo_path = self.path.join(kind);
+
+            if !memo_path.is_dir() {
+                continue;
+            }
+
+            for entry in fs::read_dir(memo_path)? {
+                let file = entry?.path();
+
+                if !used.contains(&file) {
+                    fs::remove_file(file)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Removes every cache of the site, they are all rebuilt on the next build
+pub fn clean(root_path: &Path) -> Result<()> {
+    let cache_path = root_path.join(CACHE_DIR);
+
+    if cache_path.exists() {
+        fs::remove_dir_all(cache_path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(hash: &str) -> CachedOutput {
+        CachedOutput {
+            hash: hash.into(),
+            files: Vec::new(),
+            pages: Vec::new(),
+            reads: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parts_do_not_run_into_each_other() {
+        assert_ne!(hash_parts(&["ab", "c"]), hash_parts(&["a", "bc"]));
+        assert_eq!(hash_parts(&["a", "b"]), hash_parts(&["a", "b"]));
+    }
+
+    #[test]
+    fn outputs_are_kept_for_the_next_build() {
+        let root = tempfile::tempdir().unwrap();
+        let cache = BuildCache::load(root.path()).unwrap();
+        assert!(cache.previous_output("/a/index.html").is_none());
+
+        cache.insert_output("/a/index.html", output("1"));
+        cache.save().unwrap();
+
+        let cache = BuildCache::load(root.path()).unwrap();
+        assert_eq!(cache.previous_output("/a/index.html").unwrap().hash, "1");
+
+        // Only outputs inserted by the last build are kept
+        cache.save().unwrap();
+        let cache = BuildCache::load(root.path()).unwrap();
+        assert!(cache.previous_output("/a/index.html").is_none());
+
+        fs::write(root.path().join(CACHE_DIR).join("build/outputs.json"), "{").unwrap();
+        assert!(BuildCache::load(root.path()).is_ok());
+    }
+
+    #[test]
+    fn unused_memo_files_are_pruned() {
+        let root = tempfile::tempdir().unwrap();
+        let cache = BuildCache::load(root.path()).unwrap();
+        cache.memoize("documents", "a", &1).unwrap();
+        cache.memoize("documents", "b", &2).unwrap();
+        let highlighted = cache.highlighted(&["rust", "fn"], || Ok("<b>fn</b>".into()));
+        assert_eq!(highlighted.unwrap(), "<b>fn</b>");
+        cache.save().unwrap();
+
+        let cache = BuildCache::load(root.path()).unwrap();
+        assert_eq!(cache.memoized::<i32>("documents", "a"), Some(1));
+        let highlighted = cache.highlighted(&["rust", "fn"], || unreachable!());
+        assert_eq!(highlighted.unwrap(), "<b>fn</b>");
+        cache.save().unwrap();
+
+        let memo_path = root.path().join(CACHE_DIR).join("build");
+        assert!(memo_path.join("documents/a.json").is_file());
+        assert!(!memo_path.join("documents/b.json").exists());
+        assert_eq!(
+            fs::read_dir(memo_path.join("highlighted")).unwrap().count(),
+            1
+        );
+    }
+
+    #[test]
+    fn files_unchanged_compares_content() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("menu.toml");
+        fs::write(&path, "a = 1").unwrap();
+
+        let reads = FileReads::default();
+        reads.insert(&path, &hash_file(&path));
+        let reads = reads.take();
+
+        assert!(BuildCache::load(root.path())
+            .unwrap()
+            .files_unchanged(&reads));
+
+        fs::write(&path, "a = 2").unwrap();
+        assert!(!BuildCache::load(root.path())
+            .unwrap()
+            .files_unchanged(&reads));
+
+        fs::remove_file(&path).unwrap();
+        assert!(!BuildCache::load(root.path())
+            .unwrap()
+            .files_unchanged(&reads));
+    }
+
+    #[test]
+    fn clean_removes_the_cache() {
+        let root = tempfile::tempdir().unwrap();
+        BuildCache::load(root.path()).unwrap().save().unwrap();
+        assert!(root.path().join(CACHE_DIR).is_dir());
+
+        clean(root.path()).unwrap();
+        assert!(!root.path().join(CACHE_DIR).exists());
+        clean(root.path()).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+use crate::config::SiteCompressConfig;
+use anyhow::Result;
+use flate2::{write::GzEncoder, Compression};
+use glob::glob;
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+const COMPRESSIBLE_EXTENSIONS: [&str; 5] = ["html", "css", "js", "xml", "svg"];
+
+fn is_compressible(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| COMPRESSIBLE_EXTENSIONS.contains(&e))
+        .unwrap_or(false)
+}
+
+pub fn gzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data)?;
+
+    Ok(encoder.finish()?)
+}
+
+pub fn brotli(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+
+    {
+        let mut encoder = brotli::CompressorWriter::new(&mut out, 4096, 11, 22);
+        encoder.write_all(data)?;
+    }
+
+    Ok(out)
+}
+
+// Appends an extension to the full file name, index.html becomes index.html.gz
+pub fn sibling_path(path: &Path, extension: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(extension);
+
+    path.with_file_name(file_name)
+}
+
+// Writes the precompressed siblings of the files in the out dir
+pub fn compress_outputs(out_path: &Path, config: &SiteCompressConfig) -> Result<()> {
+    if !config.gzip && !config.brotli {
+        return Ok(());
+    }
+
+    let files: Vec<PathBuf> = glob(&format!("{}/**/*", out_path.to_string_lossy()))?
+        .filter_map(|p| p.ok())
+        .filter(|p| p.is_file() && is_compressible(p))
+        .collect();
+
+    for file in files {
+        let data = fs::read(&file)?;
+
+        if (data.len() as u64) < config.min_size {
+            continue;
+        }
+
+        if config.gzip {
+            fs::write(sibling_path(&file, "gz"), gzip(&data)?)?;
+        }
+
+        if config.brotli {
+            fs::write(sibling_path(&file, "br"), brotli(&data)?)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn sibling_path_keeps_the_extension() {
+        assert_eq!(
+            sibling_path(Path::new("posts/a/index.html"), "gz"),
+            Path::new("posts/a/index.html.gz")
+        );
+    }
+
+    #[test]
+    fn gzip_and_brotli_round_trip() {
+        let data = "<p>compressed</p>".repeat(100);
+
+        let mut gunzipped = String::new();
+        flate2::read::GzDecoder::new(&gzip(data.as_bytes()).unwrap()[..])
+            .read_to_string(&mut gunzipped)
+            .unwrap();
+        assert_eq!(gunzipped, data);
+
+        let mut unbrotlied = String::new();
+        brotli::Decompressor::new(&brotli(data.as_bytes()).unwrap()[..], 4096)
+            .read_to_string(&mut unbrotlied)
+            .unwrap();
+        assert_eq!(unbrotlied, data);
+    }
+
+    #[test]
+    fn outputs_by_extension_and_size() {
+        let out = tempfile::tempdir().unwrap();
+        let large = "body { color: red; }".repeat(100);
+        fs::create_dir_all(out.path().join("posts")).unwrap();
+        fs::write(out.path().join("posts/index.html"), &large).unwrap();
+        fs::write(out.path().join("style.css"), "body{}").unwrap();
+        fs::write(out.path().join("photo.png"), &large).unwrap();
+
+        let config = SiteCompressConfig {
+            gzip: true,
+            brotli: false,
+            min_size: 100,
+        };
+        compress_outputs(out.path(), &config).unwrap();
+
+        assert!(out.path().join("posts/index.html.gz").is_file());
+        assert!(!out.path().join("posts/index.html.br").exists());
+        assert!(!out.path().join("style.css.gz").exists());
+        assert!(!out.path().join("photo.png.gz").exists());
+    }
+}
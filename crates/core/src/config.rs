@@ -1,3 +1,7 @@
 mod site;
 
-pub use site::{SiteConfig, SiteSiteConfig};
+pub use site::{
+    SiteArchivesConfig, SiteAssetsConfig, SiteCompressConfig, SiteConfig, SiteImagesConfig,
+    SiteMinifyConfig, SiteRedirectsConfig, SiteRelatedConfig, SiteSearchConfig, SiteSiteConfig,
+    SiteSummaryConfig, SiteTaxonomyConfig, SlugStyle,
+};
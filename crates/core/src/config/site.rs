@@ -1,11 +1,65 @@
+use crate::diagnostics::{Diagnostic, Diagnostics};
 use anyhow::Result;
 use serde_derive::{Deserialize, Serialize};
-use std::{fs, path::Path};
+use std::{collections::HashMap, fs, path::Path};
 
 fn default_title_sep() -> char {
     '|'
 }
 
+fn default_compress_min_size() -> u64 {
+    1024
+}
+
+fn default_search_fields() -> Vec<String> {
+    crate::search::FIELDS
+        .iter()
+        .map(|f| f.to_string())
+        .collect()
+}
+
+fn default_asset_extensions() -> Vec<String> {
+    [
+        "css", "js", "mjs", "svg", "png", "jpg", "jpeg", "gif", "webp", "avif", "ico", "woff",
+        "woff2", "ttf", "otf",
+    ]
+    .iter()
+    .map(|extension| extension.to_string())
+    .collect()
+}
+
+fn default_archives_path() -> String {
+    "/posts".into()
+}
+
+fn default_related_count() -> usize {
+    5
+}
+
+fn default_related_weight() -> f64 {
+    1.0
+}
+
+fn default_taxonomy_template() -> String {
+    "taxonomy.html".into()
+}
+
+fn default_term_template() -> String {
+    "taxonomy_term.html".into()
+}
+
+fn default_summary_words() -> usize {
+    70
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_srcset_widths() -> Vec<u32> {
+    vec![480, 960, 1440]
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SiteSiteConfig {
     pub title: Option<String>,
@@ -16,6 +70,8 @@ pub struct SiteSiteConfig {
     pub title_seperator: char,
     pub syntax_theme: Option<String>,
     pub syntax_theme_dark: Option<String>,
+    #[serde(default)]
+    pub syntax_classes: bool,
     pub copy_files: Option<Vec<[String; 2]>>,
 }
 
@@ -25,11 +81,266 @@ pub struct SiteFeedsConfig {
     pub rss: bool,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SiteAssetsConfig {
+    // Copy assets to content hashed file names
+    #[serde(default)]
+    pub fingerprint: bool,
+    // Compute subresource integrity hashes for the assets
+    #[serde(default)]
+    pub integrity: bool,
+    // Extensions of the files which are assets, other files keep their names
+    #[serde(default = "default_asset_extensions")]
+    pub extensions: Vec<String>,
+}
+
+impl Default for SiteAssetsConfig {
+    fn default() -> Self {
+        SiteAssetsConfig {
+            fingerprint: false,
+            integrity: false,
+            extensions: default_asset_extensions(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SiteMinifyConfig {
+    #[serde(default)]
+    pub html: bool,
+    #[serde(default)]
+    pub css: bool,
+    #[serde(default)]
+    pub js: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SiteCompressConfig {
+    // Write .gz siblings of the compressible files
+    #[serde(default)]
+    pub gzip: bool,
+    // Write .br siblings of the compressible files
+    #[serde(default)]
+    pub brotli: bool,
+    // Files smaller than this many bytes are left uncompressed
+    #[serde(default = "default_compress_min_size")]
+    pub min_size: u64,
+}
+
+impl Default for SiteCompressConfig {
+    fn default() -> Self {
+        SiteCompressConfig {
+            gzip: false,
+            brotli: false,
+            min_size: default_compress_min_size(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SiteArchivesConfig {
+    // Write a page per year with posts using the archive.html template
+    #[serde(default)]
+    pub enabled: bool,
+    // Also write a page per month with posts
+    #[serde(default)]
+    pub months: bool,
+    // Path the archive pages are written below
+    #[serde(default = "default_archives_path")]
+    pub path: String,
+}
+
+impl Default for SiteArchivesConfig {
+    fn default() -> Self {
+        SiteArchivesConfig {
+            enabled: false,
+            months: false,
+            path: default_archives_path(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SiteRedirectsConfig {
+    // Write a _redirects file for static hosts such as Netlify
+    #[serde(default)]
+    pub redirects_file: bool,
+    // Write redirects.map with entries for an nginx map block
+    #[serde(default)]
+    pub nginx_map: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SiteSearchConfig {
+    // Write search-index.json and search-terms.json to the out dir
+    #[serde(default)]
+    pub enabled: bool,
+    // Fields of the pages to index
+    #[serde(default = "default_search_fields")]
+    pub fields: Vec<String>,
+    // Page paths left out of the index with everything below them, such as "/drafts"
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    // Stem the terms of the inverted index as english
+    #[serde(default = "default_true")]
+    pub stemming: bool,
+}
+
+impl Default for SiteSearchConfig {
+    fn default() -> Self {
+        SiteSearchConfig {
+            enabled: false,
+            fields: default_search_fields(),
+            exclude: Vec::new(),
+            stemming: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SiteRelatedConfig {
+    // Related posts given to every post, none when zero
+    #[serde(default = "default_related_count")]
+    pub count: usize,
+    // Weight of the share of tags two posts have in common
+    #[serde(default = "default_related_weight")]
+    pub tags_weight: f64,
+    // Weight of the similarity of the words of two posts
+    #[serde(default = "default_related_weight")]
+    pub content_weight: f64,
+}
+
+impl Default for SiteRelatedConfig {
+    fn default() -> Self {
+        SiteRelatedConfig {
+            count: default_related_count(),
+            tags_weight: default_related_weight(),
+            content_weight: default_related_weight(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlugStyle {
+    // Lowercase letters and digits of any script joined by dashes
+    #[default]
+    Unicode,
+    // Lowercase ascii letters and digits joined by dashes
+    Ascii,
+    // The term as written with whitespace replaced by dashes
+    Verbatim,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SiteTaxonomyConfig {
+    // Plural name such as "categories", also the path of the taxonomy pages
+    pub name: String,
+    // Keyword the terms are read from, the uppercase name by default
+    pub keyword: Option<String>,
+    #[serde(default)]
+    pub slug: SlugStyle,
+    // Write a page listing the terms and a page per term
+    #[serde(default = "default_true")]
+    pub render: bool,
+    #[serde(default = "default_taxonomy_template")]
+    pub template: String,
+    #[serde(default = "default_term_template")]
+    pub term_template: String,
+    // Pages per term page, every page on one when zero
+    #[serde(default)]
+    pub paginate: usize,
+    // Write the enabled feeds for every term
+    #[serde(default)]
+    pub feeds: bool,
+}
+
+impl SiteTaxonomyConfig {
+    // Tags are read from #+TAGS even when they are not declared in Nuko.toml
+    fn tags() -> SiteTaxonomyConfig {
+        SiteTaxonomyConfig {
+            name: "tags".into(),
+            keyword: None,
+            slug: SlugStyle::default(),
+            render: false,
+            template: default_taxonomy_template(),
+            term_template: default_term_template(),
+            paginate: 0,
+            feeds: false,
+        }
+    }
+
+    pub fn keyword(&self) -> String {
+        self.keyword
+            .clone()
+            .unwrap_or_else(|| self.name.to_uppercase())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SiteSummaryConfig {
+    // Words in summaries of posts without a `# more` line, rounded up to the paragraph
+    #[serde(default = "default_summary_words")]
+    pub words: usize,
+}
+
+impl Default for SiteSummaryConfig {
+    fn default() -> Self {
+        SiteSummaryConfig {
+            words: default_summary_words(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SiteImagesConfig {
+    // Widths of the variants generated for responsive images
+    #[serde(default = "default_srcset_widths")]
+    pub srcset_widths: Vec<u32>,
+    // Format to convert responsive image variants to, such as "webp" or "avif"
+    pub format: Option<String>,
+}
+
+impl Default for SiteImagesConfig {
+    fn default() -> Self {
+        SiteImagesConfig {
+            srcset_widths: default_srcset_widths(),
+            format: None,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SiteConfig {
     pub site: SiteSiteConfig,
     #[serde(default)]
     pub feeds: SiteFeedsConfig,
+    #[serde(default)]
+    pub assets: SiteAssetsConfig,
+    #[serde(default)]
+    pub images: SiteImagesConfig,
+    #[serde(default)]
+    pub minify: SiteMinifyConfig,
+    #[serde(default)]
+    pub compress: SiteCompressConfig,
+    #[serde(default)]
+    pub search: SiteSearchConfig,
+    #[serde(default)]
+    pub redirects: SiteRedirectsConfig,
+    #[serde(default)]
+    pub archives: SiteArchivesConfig,
+    #[serde(default)]
+    pub related: SiteRelatedConfig,
+    #[serde(default)]
+    pub taxonomies: Vec<SiteTaxonomyConfig>,
+    #[serde(default)]
+    pub summary: SiteSummaryConfig,
+    // Permalink patterns for the pages of a section, such as posts = "/posts/:year/:slug/"
+    #[serde(default)]
+    pub permalinks: HashMap<String, String>,
+    // Maps source block languages to syntax names, extensions or tokens
+    #[serde(default)]
+    pub syntax_aliases: HashMap<String, String>,
 }
 
 impl SiteConfig {
@@ -39,9 +350,32 @@ impl SiteConfig {
         Ok(config)
     }
 
+    // The declared taxonomies and tags
+    pub fn taxonomies(&self) -> Vec<SiteTaxonomyConfig> {
+        let mut taxonomies = self.taxonomies.clone();
+
+        if !taxonomies.iter().any(|taxonomy| taxonomy.name == "tags") {
+            taxonomies.insert(0, SiteTaxonomyConfig::tags());
+        }
+
+        taxonomies
+    }
+
     pub fn read_file(path: &Path) -> Result<SiteConfig> {
         let text = fs::read_to_string(path)?;
 
-        Self::parse(&text)
+        toml::from_str(&text).map_err(|err| {
+            // The location is shown in the snippet instead of the message
+            let message = err.to_string();
+            let message = message.split(" at line ").next().unwrap_or(&message);
+            let diagnostic = Diagnostic::error(format!("invalid config: {}", message), path);
+
+            let diagnostic = match err.line_col() {
+                Some((line, column)) => diagnostic.at_line_col(&text, line, column),
+                None => diagnostic,
+            };
+
+            Diagnostics(vec![diagnostic]).into()
+        })
     }
 }
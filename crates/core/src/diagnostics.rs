@@ -0,0 +1,229 @@
+use serde_derive::{Deserialize, Serialize};
+use std::{error::Error as StdError, fmt, path::Path};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Location {
+    // Both are one based
+    pub line: usize,
+    pub column: usize,
+    pub snippet: String,
+    pub length: usize,
+}
+
+// An error or warning in a source file, rendered like a compiler error
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub file: String,
+    pub location: Option<Location>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, file: &Path) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            file: file.to_string_lossy().into(),
+            location: None,
+            help: None,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, file: &Path) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(message, file)
+        }
+    }
+
+    // Points at a byte range of the source text
+    pub fn at(mut self, text: &str, offset: usize, length: usize) -> Diagnostic {
+        // Back up to the start of the character the byte is in
+        let mut offset = offset.min(text.len());
+        while !text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = text[offset..].find('\n').map_or(text.len(), |i| offset + i);
+
+        self.location = Some(Location {
+            line: text[..offset].matches('\n').count() + 1,
+            column: text[line_start..offset].chars().count() + 1,
+            snippet: text[line_start..line_end].trim_end().into(),
+            length: text[offset..line_end].chars().take(length).count().max(1),
+        });
+        self
+    }
+
+    // Points at a zero based line and column, toml counts the column in bytes
+    pub fn at_line_col(self, text: &str, line: usize, column: usize) -> Diagnostic {
+        let line_offset: usize = text.split('\n').take(line).map(|l| l.len() + 1).sum();
+        let line_offset = line_offset.min(text.len());
+        let line_len = text[line_offset..]
+            .find('\n')
+            .unwrap_or(text.len() - line_offset);
+
+        self.at(text, line_offset + column.min(line_len), 1)
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Diagnostic {
+        self.help = Some(help.into());
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+
+        match &self.location {
+            Some(location) => {
+                let gutter = " ".repeat(location.line.to_string().len());
+
+                write!(
+                    f,
+                    "\n{}--> {}:{}:{}",
+                    gutter, self.file, location.line, location.column
+                )?;
+                write!(f, "\n{} |", gutter)?;
+                write!(f, "\n{} | {}", location.line, location.snippet)?;
+                // Tabs before the caret are kept so it lines up however wide they are shown
+                let indent: String = location
+                    .snippet
+                    .chars()
+                    .chain(std::iter::repeat(' '))
+                    .take(location.column - 1)
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+
+                write!(
+                    f,
+                    "\n{} | {}{}",
+                    gutter,
+                    indent,
+                    "^".repeat(location.length)
+                )?;
+
+                if let Some(help) = &self.help {
+                    write!(f, "\n{} = help: {}", gutter, help)?;
+                }
+            }
+            None => {
+                write!(f, "\n --> {}", self.file)?;
+
+                if let Some(help) = &self.help {
+                    write!(f, "\n  = help: {}", help)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl StdError for Diagnostic {}
+
+// Every diagnostic found in one pass, so they can be fixed together
+#[derive(Debug)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, diagnostic) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "\n\n")?;
+            }
+
+            write!(f, "{}: {}", diagnostic.severity, diagnostic)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StdError for Diagnostics {}
+
+// Splits diagnostics out of an error so they can be collected with others
+pub fn collect_diagnostics<T>(
+    diagnostics: &mut Vec<Diagnostic>,
+    result: anyhow::Result<T>,
+) -> anyhow::Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(err) => match err.downcast::<Diagnostics>() {
+            Ok(found) => {
+                diagnostics.extend(found.0);
+
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn at_line_col_counts_bytes() {
+        let text = "title = \"é\"\nname = \"ü\" x\n";
+        // The byte column of "x", which is one character less after the two byte "ü"
+        let diagnostic =
+            Diagnostic::error("error", Path::new("nuko.toml")).at_line_col(text, 1, 12);
+        let location = diagnostic.location.unwrap();
+
+        assert_eq!(location.line, 2);
+        assert_eq!(location.column, 12);
+        assert_eq!(location.snippet, "name = \"ü\" x");
+    }
+
+    #[test]
+    fn at_backs_up_to_char_boundary() {
+        let text = "#+TITLE: Ünïcode\n";
+        // Inside the two byte "Ü"
+        let diagnostic = Diagnostic::error("error", Path::new("a.org")).at(text, 10, 3);
+        let location = diagnostic.location.unwrap();
+
+        assert_eq!(location.line, 1);
+        assert_eq!(location.column, 10);
+        assert_eq!(location.length, 3);
+
+        let diagnostic = Diagnostic::error("error", Path::new("a.org")).at(text, 100, 1);
+        assert_eq!(diagnostic.location.unwrap().line, 2);
+    }
+
+    #[test]
+    fn caret_keeps_tabs() {
+        let text = "a\n\t\tkey = value\n";
+        let diagnostic = Diagnostic::error("invalid key", Path::new("a.org")).at(text, 4, 3);
+
+        assert_eq!(
+            diagnostic.to_string(),
+            "invalid key\n\
+             \x20--> a.org:2:3\n\
+             \x20 |\n\
+             2 | \t\tkey = value\n\
+             \x20 | \t\t^^^"
+        );
+    }
+}
@@ -1,8 +1,13 @@
+use crate::cache::{hash_folder, CACHE_DIR};
 use anyhow::Result;
-use std::path::Path;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{collections::HashMap, fs, path::Path, sync::Arc};
 use syntect::{
+    dumps::{dump_to_file, from_dump_file},
     highlighting::{Theme, ThemeSet},
+    html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator},
     parsing::{SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
 };
 use thiserror::Error;
 
@@ -10,46 +15,131 @@ lazy_static! {
     static ref DEFAULT_THEME: String = String::from("base16-ocean.dark");
 }
 
+// Loads a binary dump from the cache if the hash matches, otherwise builds and caches it
+fn load_cached<T, F>(cache_path: &Path, name: &str, hash: &str, build: F) -> Result<T>
+where
+    T: Serialize + DeserializeOwned,
+    F: FnOnce() -> Result<T>,
+{
+    let dump_path = cache_path.join(format!("{}.dump", name));
+    let hash_path = cache_path.join(format!("{}.sha256", name));
+
+    if dump_path.is_file() && fs::read_to_string(&hash_path).ok().as_deref() == Some(hash) {
+        // A broken dump is rebuilt below
+        if let Ok(value) = from_dump_file(&dump_path) {
+            return Ok(value);
+        }
+    }
+
+    let value = build()?;
+
+    fs::create_dir_all(cache_path)?;
+    dump_to_file(&value, &dump_path)?;
+    fs::write(hash_path, hash)?;
+
+    Ok(value)
+}
+
 pub struct Highlighting {
     syntax: Option<String>,
-    syntax_set: SyntaxSet,
-    theme_set: ThemeSet,
+    classed: bool,
+    aliases: HashMap<String, String>,
+    syntax_set: Arc<SyntaxSet>,
+    theme_set: Arc<ThemeSet>,
+    // Hashes of the custom syntaxes and themes
+    sets_hash: String,
 }
 
 impl Highlighting {
     pub fn new(root_path: &Path, theme: Option<String>) -> Result<Highlighting> {
-        let mut syntax_set = SyntaxSet::load_defaults_newlines();
-        let mut theme_set = ThemeSet::load_defaults();
-
         let highlighting_path = root_path.join("highlighting");
+        let cache_path = root_path.join(CACHE_DIR).join("highlighting");
+
+        let highlighting_syntaxes_path = highlighting_path.join("syntaxes");
 
-        if highlighting_path.is_dir() {
-            let highlighting_syntaxes_path = highlighting_path.join("syntaxes");
+        let mut sets_hash = String::from("default");
 
-            if highlighting_syntaxes_path.is_dir() {
-                let mut builder = syntax_set.into_builder();
+        let syntax_set = if highlighting_syntaxes_path.is_dir() {
+            let hash = hash_folder(&highlighting_syntaxes_path)?;
+            sets_hash = hash.clone();
+
+            load_cached(&cache_path, "syntaxes", &hash, || {
+                let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
                 builder.add_from_folder(&highlighting_syntaxes_path, true)?;
-                syntax_set = builder.build();
-            }
 
-            let highlighting_themes_path = highlighting_path.join("themes");
+                Ok(builder.build())
+            })?
+        } else {
+            SyntaxSet::load_defaults_newlines()
+        };
+
+        let highlighting_themes_path = highlighting_path.join("themes");
 
-            if highlighting_themes_path.is_dir() {
+        let theme_set = if highlighting_themes_path.is_dir() {
+            let hash = hash_folder(&highlighting_themes_path)?;
+            sets_hash = format!("{} {}", sets_hash, hash);
+
+            load_cached(&cache_path, "themes", &hash, || {
+                let mut theme_set = ThemeSet::load_defaults();
                 theme_set.add_from_folder(&highlighting_themes_path)?;
-            }
-        }
+
+                Ok(theme_set)
+            })?
+        } else {
+            ThemeSet::load_defaults()
+        };
 
         Ok(Highlighting {
             syntax: theme,
-            syntax_set,
-            theme_set,
+            classed: false,
+            aliases: HashMap::new(),
+            syntax_set: Arc::new(syntax_set),
+            theme_set: Arc::new(theme_set),
+            sets_hash,
         })
     }
 
-    pub fn find_syntax_by_name(&self, name: &str) -> Result<&SyntaxReference> {
+    // Creates a highlighting with another theme which shares the loaded syntaxes and themes
+    pub fn with_theme(&self, theme: Option<String>) -> Highlighting {
+        Highlighting {
+            syntax: theme,
+            classed: self.classed,
+            aliases: self.aliases.clone(),
+            syntax_set: self.syntax_set.clone(),
+            theme_set: self.theme_set.clone(),
+            sets_hash: self.sets_hash.clone(),
+        }
+    }
+
+    pub fn set_classed(&mut self, classed: bool) {
+        self.classed = classed;
+    }
+
+    pub fn classed(&self) -> bool {
+        self.classed
+    }
+
+    pub fn set_aliases(&mut self, aliases: HashMap<String, String>) {
+        self.aliases = aliases;
+    }
+
+    pub fn aliases(&self) -> &HashMap<String, String> {
+        &self.aliases
+    }
+
+    // Looks up a language by alias, syntax name, file extension and lastly token
+    pub fn find_syntax(&self, language: &str) -> Result<&SyntaxReference> {
+        let name = self
+            .aliases
+            .get(language)
+            .map(|s| s.as_str())
+            .unwrap_or(language);
+
         self.syntax_set
             .find_syntax_by_name(name)
-            .ok_or_else(|| HighlightingError::UnknownSyntax(name.into()).into())
+            .or_else(|| self.syntax_set.find_syntax_by_extension(name))
+            .or_else(|| self.syntax_set.find_syntax_by_token(name))
+            .ok_or_else(|| HighlightingError::UnknownSyntax(language.into()).into())
     }
 
     pub fn theme(&self) -> Result<&Theme> {
@@ -60,9 +150,41 @@ impl Highlighting {
             .ok_or_else(|| HighlightingError::UnknownSyntaxTheme(name.into()).into())
     }
 
+    // Identifies everything which changes the highlighted output besides the code
+    pub fn cache_key(&self) -> String {
+        format!("{} {:?} {}", self.sets_hash, self.syntax, self.classed)
+    }
+
     pub fn syntaxes(&self) -> &SyntaxSet {
         &self.syntax_set
     }
+
+    pub fn themes(&self) -> &ThemeSet {
+        &self.theme_set
+    }
+
+    // Highlight code into spans with css classes instead of inline styles
+    pub fn highlight_classed(&self, syntax: &SyntaxReference, code: &str) -> String {
+        let mut generator = ClassedHTMLGenerator::new_with_class_style(
+            syntax,
+            &self.syntax_set,
+            ClassStyle::Spaced,
+        );
+
+        for line in LinesWithEndings::from(code) {
+            generator.parse_html_for_line_which_includes_newline(line);
+        }
+
+        generator.finalize()
+    }
+
+    // Stylesheet for the classes emitted by highlight_classed
+    pub fn theme_css(&self) -> Result<String> {
+        Ok(css_for_theme_with_class_style(
+            self.theme()?,
+            ClassStyle::Spaced,
+        ))
+    }
 }
 
 #[derive(Error, Debug)]
@@ -72,3 +194,113 @@ pub enum HighlightingError {
     #[error("cannot find syntax theme \"{0}\"")]
     UnknownSyntaxTheme(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classed_highlighting_with_stylesheet() {
+        let root = tempfile::tempdir().unwrap();
+        let mut highlighting = Highlighting::new(root.path(), None).unwrap();
+        let inline_key = highlighting.cache_key();
+        highlighting.set_classed(true);
+
+        let syntax = highlighting.find_syntax("rust").unwrap();
+        let html = highlighting.highlight_classed(syntax, "fn main() {}\n");
+
+        assert!(html.contains("<span class=\"source rust\">"), "{}", html);
+        assert!(!html.contains("style="), "{}", html);
+        let css = highlighting.theme_css().unwrap();
+        assert!(css.contains(".comment"), "{}", css);
+        assert_ne!(highlighting.cache_key(), inline_key);
+
+        let dark = highlighting.with_theme(Some("base16-ocean.light".into()));
+        assert!(dark.classed());
+        assert_ne!(dark.theme_css().unwrap(), highlighting.theme_css().unwrap());
+        assert!(highlighting
+            .with_theme(Some("missing".into()))
+            .theme_css()
+            .is_err());
+    }
+
+    #[test]
+    fn find_syntax_by_alias_name_and_extension() {
+        let root = tempfile::tempdir().unwrap();
+        let mut highlighting = Highlighting::new(root.path(), None).unwrap();
+        highlighting.set_aliases([("shell".to_string(), "bash".to_string())].into());
+
+        let name = |language: &str| highlighting.find_syntax(language).unwrap().name.clone();
+
+        assert_eq!(name("shell"), "Bourne Again Shell (bash)");
+        assert_eq!(name("Rust"), "Rust");
+        assert_eq!(name("rs"), "Rust");
+        assert_eq!(
+            highlighting.find_syntax("nope").unwrap_err().to_string(),
+            "cannot find syntax highlighting for language \"nope\""
+        );
+    }
+
+    #[test]
+    fn load_cached_rebuilds_when_the_hash_changes() {
+        let cache = tempfile::tempdir().unwrap();
+        let load = |hash: &str, value: u32| load_cached(cache.path(), "values", hash, || Ok(value));
+
+        assert_eq!(load("a", 1).unwrap(), 1);
+        assert_eq!(load("a", 2).unwrap(), 1);
+        assert_eq!(load("b", 3).unwrap(), 3);
+
+        // A broken dump is built again
+        fs::write(cache.path().join("values.dump"), "broken").unwrap();
+        assert_eq!(load("b", 4).unwrap(), 4);
+    }
+
+    const PLAIN_THEME: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+    <key>name</key>
+    <string>Plain</string>
+    <key>settings</key>
+    <array>
+        <dict>
+            <key>settings</key>
+            <dict>
+                <key>foreground</key>
+                <string>#000000</string>
+            </dict>
+        </dict>
+    </array>
+</dict>
+</plist>
+"#;
+
+    #[test]
+    fn custom_themes_are_cached() {
+        let root = tempfile::tempdir().unwrap();
+        let themes_path = root.path().join("highlighting/themes");
+        fs::create_dir_all(&themes_path).unwrap();
+
+        let default_key = Highlighting::new(root.path(), None).unwrap().cache_key();
+        fs::write(themes_path.join("plain.tmTheme"), PLAIN_THEME).unwrap();
+
+        let highlighting = Highlighting::new(root.path(), Some("plain".into())).unwrap();
+        assert!(highlighting.theme().is_ok());
+        assert!(root
+            .path()
+            .join(CACHE_DIR)
+            .join("highlighting/themes.dump")
+            .is_file());
+
+        // Editing a theme changes the key of the highlighted code
+        let key = highlighting.cache_key();
+        fs::write(
+            themes_path.join("plain.tmTheme"),
+            PLAIN_THEME.replace("#000000", "#111111"),
+        )
+        .unwrap();
+
+        let highlighting = Highlighting::new(root.path(), Some("plain".into())).unwrap();
+        assert_ne!(highlighting.cache_key(), key);
+        assert_ne!(key, default_key);
+    }
+}
@@ -0,0 +1,433 @@
+use crate::{
+    cache::{hash_bytes, FileReads, CACHE_DIR},
+    config::SiteImagesConfig,
+};
+use anyhow::Result;
+use image::{imageops::FilterType, ImageFormat};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+
+const OUT_DIR: &str = "processed_images";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResizeOp {
+    // Scale down to fit inside the size, keeping the aspect ratio
+    Fit,
+    // Scale and crop to exactly fill the size
+    Fill,
+    // Crop the center of the image without scaling
+    Crop,
+}
+
+impl FromStr for ResizeOp {
+    type Err = ImagesError;
+
+    fn from_str(s: &str) -> Result<ResizeOp, ImagesError> {
+        match s {
+            "fit" => Ok(ResizeOp::Fit),
+            "fill" => Ok(ResizeOp::Fill),
+            "crop" => Ok(ResizeOp::Crop),
+            _ => Err(ImagesError::UnknownResizeOp(s.into())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProcessedImage {
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+// An image read from the out dir with the hash of its content
+struct SourceImage {
+    src: String,
+    path: PathBuf,
+    content: Vec<u8>,
+    hash: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedSize {
+    width: u32,
+    height: u32,
+}
+
+pub struct ImageProcessor {
+    config: SiteImagesConfig,
+    base_url: String,
+    out_path: PathBuf,
+    cache_path: PathBuf,
+    processed: Mutex<HashMap<String, (String, ProcessedImage)>>,
+    // Out files used since the last take_used, so skipped outputs can keep them
+    used: Mutex<Vec<String>>,
+    // Source images read, so cached outputs are rendered again when they change
+    reads: Arc<FileReads>,
+}
+
+impl ImageProcessor {
+    pub fn new(
+        root_path: &Path,
+        out_path: &Path,
+        base_url: &str,
+        config: SiteImagesConfig,
+        reads: Arc<FileReads>,
+    ) -> ImageProcessor {
+        ImageProcessor {
+            config,
+            base_url: base_url.into(),
+            out_path: out_path.into(),
+            cache_path: root_path.join(CACHE_DIR).join("images"),
+            processed: Mutex::new(HashMap::new()),
+            used: Mutex::new(Vec::new()),
+            reads,
+        }
+    }
+
+    // Reads an image in the out dir once, its hash is the base of every variant
+    fn source(&self, src: &str) -> Result<SourceImage> {
+        let src = src.trim_start_matches('/');
+        let path = self.out_path.join(src);
+
+        if !path.is_file() {
+            return Err(ImagesError::MissingImage(src.into()).into());
+        }
+
+        let content = fs::read(&path)?;
+        let hash = hash_bytes(&content);
+        self.reads.insert(&path, &hash);
+
+        Ok(SourceImage {
+            src: src.into(),
+            path,
+            content,
+            hash,
+        })
+    }
+
+    // Resizes an image in the out dir, results are cached by source hash and operation
+    pub fn resize(
+        &self,
+        src: &str,
+        op: ResizeOp,
+        width: Option<u32>,
+        height: Option<u32>,
+        format: Option<&str>,
+    ) -> Result<ProcessedImage> {
+        let source = self.source(src)?;
+
+        self.resize_source(&source, op, width, height, format)
+    }
+
+    fn resize_source(
+        &self,
+        source: &SourceImage,
+        op: ResizeOp,
+        width: Option<u32>,
+        height: Option<u32>,
+        format: Option<&str>,
+    ) -> Result<ProcessedImage> {
+        let src = &source.src;
+
+        if width == Some(0) || height == Some(0) {
+            return Err(ImagesError::ZeroSize(src.clone()).into());
+        }
+
+        if op != ResizeOp::Fit && (width.is_none() || height.is_none()) {
+            return Err(ImagesError::MissingSize(src.clone()).into());
+        }
+
+        let format = match format {
+            Some(format) => ImageFormat::from_extension(format)
+                .ok_or_else(|| ImagesError::UnknownFormat(format.into()))?,
+            None => ImageFormat::from_path(&source.path)?,
+        };
+        let extension = format.extensions_str()[0];
+
+        let mut hasher = Sha256::new();
+        hasher.update(&source.hash);
+        hasher.update(format!("{:?} {:?} {:?} {}", op, width, height, extension));
+        let hash = format!("{:x}", hasher.finalize());
+
+        if let Some((out_file, processed)) = self.processed.lock().unwrap().get(&hash) {
+            self.used.lock().unwrap().push(out_file.clone());
+
+            return Ok(processed.clone());
+        }
+
+        let cache_file = self.cache_path.join(format!("{}.{}", hash, extension));
+        let cache_size_file = self.cache_path.join(format!("{}.json", hash));
+
+        let size: CachedSize = if cache_file.is_file() && cache_size_file.is_file() {
+            serde_json::from_str(&fs::read_to_string(&cache_size_file)?)?
+        } else {
+            let image = image::load_from_memory(&source.content)?;
+
+            let resized = match op {
+                ResizeOp::Fit => {
+                    // Never scale up
+                    let width = width.unwrap_or(u32::MAX).min(image.width());
+                    let height = height.unwrap_or(u32::MAX).min(image.height());
+
+                    image.resize(width, height, FilterType::Lanczos3)
+                }
+                ResizeOp::Fill => {
+                    // Never scale up, a size larger than the image is shrunk to the largest
+                    // one with the same aspect ratio which fits
+                    let (width, height) = (width.unwrap(), height.unwrap());
+                    let scale = (image.width() as f64 / width as f64)
+                        .min(image.height() as f64 / height as f64)
+                        .min(1.0);
+                    let width = ((width as f64 * scale).round() as u32).max(1);
+                    let height = ((height as f64 * scale).round() as u32).max(1);
+
+                    image.resize_to_fill(width, height, FilterType::Lanczos3)
+                }
+                ResizeOp::Crop => {
+                    let width = width.unwrap().min(image.width());
+                    let height = height.unwrap().min(image.height());
+
+                    image.crop_imm(
+                        (image.width() - width) / 2,
+                        (image.height() - height) / 2,
+                        width,
+                        height,
+                    )
+                }
+            };
+
+            fs::create_dir_all(&self.cache_path)?;
+            resized.save_with_format(&cache_file, format)?;
+
+            let size = CachedSize {
+                width: resized.width(),
+                height: resized.height(),
+            };
+            fs::write(&cache_size_file, serde_json::to_string(&size)?)?;
+
+            size
+        };
+
+        let stem = Path::new(src)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let out_file = format!("{}/{}.{}.{}", OUT_DIR, stem, &hash[..16], extension);
+
+        fs::create_dir_all(self.out_path.join(OUT_DIR))?;
+        fs::copy(&cache_file, self.out_path.join(&out_file))?;
+
+        let processed = ProcessedImage {
+            url: format!("{}/{}", self.base_url, out_file),
+            width: size.width,
+            height: size.height,
+        };
+
+        self.used.lock().unwrap().push(out_file.clone());
+        self.processed
+            .lock()
+            .unwrap()
+            .insert(hash, (out_file, processed.clone()));
+
+        Ok(processed)
+    }
+
+    pub fn take_used(&self) -> Vec<String> {
+        std::mem::take(&mut *self.used.lock().unwrap())
+    }
+
+    // Variants for the configured srcset widths which are not larger than the source
+    pub fn srcset(&self, src: &str) -> Result<Vec<ProcessedImage>> {
+        let source = self.source(src)?;
+        let mut variants: Vec<ProcessedImage> = Vec::new();
+
+        for width in &self.config.srcset_widths {
+            let variant = self.resize_source(
+                &source,
+                ResizeOp::Fit,
+                Some(*width),
+                None,
+                self.config.format.as_deref(),
+            )?;
+
+            if variants.iter().any(|v| v.width == variant.width) {
+                continue;
+            }
+
+            variants.push(variant);
+        }
+
+        Ok(variants)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ImagesError {
+    #[error("no image file found at \"{0}\"")]
+    MissingImage(String),
+    #[error("resizing \"{0}\" by fill or crop requires both width and height")]
+    MissingSize(String),
+    #[error("resizing \"{0}\" requires a width and height above zero")]
+    ZeroSize(String),
+    #[error("unknown resize operation \"{0}\", expected fit, fill or crop")]
+    UnknownResizeOp(String),
+    #[error("unknown image format \"{0}\"")]
+    UnknownFormat(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn processor(widths: Vec<u32>) -> (tempfile::TempDir, ImageProcessor) {
+        let root = tempfile::tempdir().unwrap();
+        let out_path = root.path().join("out");
+        fs::create_dir_all(&out_path).unwrap();
+        RgbImage::from_pixel(400, 200, Rgb([200, 100, 50]))
+            .save(out_path.join("photo.png"))
+            .unwrap();
+
+        let config = SiteImagesConfig {
+            srcset_widths: widths,
+            format: None,
+        };
+        let images = ImageProcessor::new(
+            root.path(),
+            &out_path,
+            "https://example.com",
+            config,
+            Arc::default(),
+        );
+
+        (root, images)
+    }
+
+    fn size(image: &ProcessedImage) -> (u32, u32) {
+        (image.width, image.height)
+    }
+
+    #[test]
+    fn resize_ops() {
+        let (_root, images) = processor(Vec::new());
+
+        let fit = images.resize("/photo.png", ResizeOp::Fit, Some(100), None, None);
+        assert_eq!(size(&fit.unwrap()), (100, 50));
+
+        let fill = images.resize("photo.png", ResizeOp::Fill, Some(100), Some(100), None);
+        assert_eq!(size(&fill.unwrap()), (100, 100));
+
+        let crop = images.resize("photo.png", ResizeOp::Crop, Some(50), Some(500), None);
+        assert_eq!(size(&crop.unwrap()), (50, 200));
+    }
+
+    #[test]
+    fn resize_never_scales_up() {
+        let (_root, images) = processor(Vec::new());
+
+        let fit = images.resize("photo.png", ResizeOp::Fit, Some(800), Some(800), None);
+        assert_eq!(size(&fit.unwrap()), (400, 200));
+
+        // The 2:1 box shrinks to fit the image instead of growing it
+        let fill = images.resize("photo.png", ResizeOp::Fill, Some(1000), Some(500), None);
+        assert_eq!(size(&fill.unwrap()), (400, 200));
+
+        let fill = images.resize("photo.png", ResizeOp::Fill, Some(600), Some(600), None);
+        assert_eq!(size(&fill.unwrap()), (200, 200));
+    }
+
+    #[test]
+    fn resize_errors() {
+        let (_root, images) = processor(Vec::new());
+
+        for (op, width, height) in [
+            (ResizeOp::Fit, Some(0), None),
+            (ResizeOp::Fill, Some(100), Some(0)),
+            (ResizeOp::Fill, Some(100), None),
+            (ResizeOp::Crop, None, Some(100)),
+        ] {
+            assert!(images.resize("photo.png", op, width, height, None).is_err());
+        }
+
+        assert!(images
+            .resize("missing.png", ResizeOp::Fit, Some(10), None, None)
+            .is_err());
+        assert!(images
+            .resize("photo.png", ResizeOp::Fit, Some(10), None, Some("doc"))
+            .is_err());
+    }
+
+    #[test]
+    fn resize_caches_variants() {
+        let (root, images) = processor(Vec::new());
+
+        let first = images
+            .resize("photo.png", ResizeOp::Fit, Some(100), None, Some("webp"))
+            .unwrap();
+        let second = images
+            .resize("photo.png", ResizeOp::Fit, Some(100), None, Some("webp"))
+            .unwrap();
+
+        assert_eq!(first.url, second.url);
+        assert!(first
+            .url
+            .starts_with("https://example.com/processed_images/photo."));
+        assert!(first.url.ends_with(".webp"));
+        assert_eq!(images.take_used().len(), 2);
+
+        // A new processor finds the variant in the cache dir
+        let out_path = root.path().join("out");
+        fs::remove_dir_all(out_path.join(OUT_DIR)).unwrap();
+        let images = ImageProcessor::new(
+            root.path(),
+            &out_path,
+            "https://example.com",
+            SiteImagesConfig::default(),
+            Arc::default(),
+        );
+        let cached = images
+            .resize("photo.png", ResizeOp::Fit, Some(100), None, Some("webp"))
+            .unwrap();
+
+        assert_eq!(cached.url, first.url);
+        assert!(out_path
+            .join(cached.url.trim_start_matches("https://example.com/"))
+            .is_file());
+    }
+
+    #[test]
+    fn srcset_skips_widths_above_the_source() {
+        let (_root, images) = processor(vec![100, 300, 500, 800]);
+
+        let widths: Vec<u32> = images
+            .srcset("photo.png")
+            .unwrap()
+            .iter()
+            .map(|variant| variant.width)
+            .collect();
+
+        assert_eq!(widths, [100, 300, 400]);
+    }
+
+    #[test]
+    fn records_source_reads() {
+        let (root, images) = processor(vec![100]);
+        images.srcset("photo.png").unwrap();
+
+        let source = root.path().join("out/photo.png");
+        let reads = images.reads.take();
+
+        assert_eq!(reads.len(), 1);
+        assert_eq!(reads[0].path, source);
+        assert_eq!(reads[0].hash, crate::cache::hash_file(&source));
+        assert!(images.reads.take().is_empty());
+    }
+}
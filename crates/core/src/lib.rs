@@ -1,12 +1,25 @@
 #[macro_use]
 extern crate lazy_static;
 
+pub mod archetypes;
+pub mod assets;
+pub mod cache;
+pub mod compress;
 pub mod config;
+pub mod diagnostics;
 pub mod highlighting;
+pub mod images;
+pub mod minify;
 pub mod org_emitter;
 pub mod page;
 pub mod posts;
+pub mod redirects;
+pub mod report;
+pub mod search;
+pub mod sections;
 pub mod site;
 pub mod sitemap;
+pub mod taxonomies;
+pub mod template_deps;
 pub mod template_fns;
 pub mod toc;
@@ -0,0 +1,135 @@
+use crate::config::SiteMinifyConfig;
+use anyhow::Result;
+use glob::glob;
+use lightningcss::stylesheet::{MinifyOptions, ParserOptions, PrinterOptions, StyleSheet};
+use minify_js::{Session, TopLevelMode};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+// Whitespace inside pre elements such as highlighted code blocks is kept as is
+pub fn minify_html(html: &str, config: &SiteMinifyConfig) -> String {
+    let mut cfg = minify_html::Cfg::spec_compliant();
+    cfg.minify_css = config.css;
+    cfg.minify_js = config.js;
+
+    String::from_utf8_lossy(&minify_html::minify(html.as_bytes(), &cfg)).into()
+}
+
+pub fn minify_css(css: &str) -> Result<String> {
+    let mut stylesheet = StyleSheet::parse(css, ParserOptions::default())
+        .map_err(|e| MinifyError::Css(e.to_string()))?;
+
+    stylesheet
+        .minify(MinifyOptions::default())
+        .map_err(|e| MinifyError::Css(e.to_string()))?;
+
+    let output = stylesheet
+        .to_css(PrinterOptions {
+            minify: true,
+            ..Default::default()
+        })
+        .map_err(|e| MinifyError::Css(e.to_string()))?;
+
+    Ok(output.code)
+}
+
+pub fn minify_js(js: &str) -> Result<String> {
+    let session = Session::new();
+    let mut out = Vec::new();
+
+    minify_js::minify(&session, TopLevelMode::Global, js.as_bytes(), &mut out)
+        .map_err(|e| MinifyError::Js(format!("{:?}", e)))?;
+
+    Ok(String::from_utf8_lossy(&out).into())
+}
+
+// Minifies the css and js files in the out dir in place
+pub fn minify_assets(out_path: &Path, config: &SiteMinifyConfig) -> Result<()> {
+    let files: Vec<PathBuf> = glob(&format!("{}/**/*", out_path.to_string_lossy()))?
+        .filter_map(|p| p.ok())
+        .filter(|p| p.is_file())
+        .collect();
+
+    for file in files {
+        let minified = match file.extension().and_then(|e| e.to_str()) {
+            Some("css") if config.css => minify_css(&fs::read_to_string(&file)?),
+            Some("js") if config.js => minify_js(&fs::read_to_string(&file)?),
+            _ => continue,
+        }
+        .map_err(|e| MinifyError::File(file.to_string_lossy().into(), e.to_string()))?;
+
+        fs::write(&file, minified)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Error, Debug)]
+pub enum MinifyError {
+    #[error("error minifying css: {0}")]
+    Css(String),
+    #[error("error minifying js: {0}")]
+    Js(String),
+    #[error("error minifying \"{0}\": {1}")]
+    File(String, String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(css: bool, js: bool) -> SiteMinifyConfig {
+        SiteMinifyConfig {
+            html: true,
+            css,
+            js,
+        }
+    }
+
+    #[test]
+    fn html_keeps_preformatted_text() {
+        let html = "<div>\n  <p>Text</p>\n</div>\n<pre>  a\n    b\n</pre>";
+
+        let minified = minify_html(html, &config(false, false));
+
+        assert!(minified.starts_with("<div><p>Text"), "{}", minified);
+        assert!(minified.contains("<pre>  a\n    b\n</pre>"), "{}", minified);
+    }
+
+    #[test]
+    fn css_and_js() {
+        assert_eq!(
+            minify_css("body {\n  color: #ff0000;\n}\n").unwrap(),
+            "body{color:red}"
+        );
+        // Unclosed blocks are closed like browsers do
+        assert_eq!(minify_css("body { color: red").unwrap(), "body{color:red}");
+
+        let js = minify_js("function add(first, second) {\n  return first + second;\n}\n").unwrap();
+        assert!(js.len() < 40, "{}", js);
+        assert!(minify_js("function (").is_err());
+    }
+
+    #[test]
+    fn assets_by_extension() {
+        let out = tempfile::tempdir().unwrap();
+        let css = "body {\n  color: red;\n}\n";
+        fs::write(out.path().join("style.css"), css).unwrap();
+        fs::write(out.path().join("app.js"), "var a = 1;\n").unwrap();
+        fs::write(out.path().join("notes.txt"), "a  b\n").unwrap();
+
+        minify_assets(out.path(), &config(true, false)).unwrap();
+
+        let read = |name: &str| fs::read_to_string(out.path().join(name)).unwrap();
+        assert_eq!(read("style.css"), "body{color:red}");
+        assert_eq!(read("app.js"), "var a = 1;\n");
+        assert_eq!(read("notes.txt"), "a  b\n");
+
+        fs::write(out.path().join("broken.js"), "function (").unwrap();
+        let err = minify_assets(out.path(), &config(false, true)).unwrap_err();
+        assert!(err.to_string().contains("broken.js"), "{}", err);
+    }
+}
@@ -1,4 +1,7 @@
-use crate::{highlighting::Highlighting, toc::Toc};
+use crate::{
+    cache::BuildCache, highlighting::Highlighting, images::ImageProcessor, page::PageResource,
+    toc::Toc,
+};
 use anyhow::Result;
 use orgize::{
     elements::{Element, Link},
@@ -10,14 +13,102 @@ use syntect::{
 };
 use thiserror::Error;
 
+pub struct EmitOptions<'a> {
+    pub base_url: &'a str,
+    pub highlighting: &'a Highlighting,
+    pub highlighting_dark: Option<&'a Highlighting>,
+    pub images: &'a ImageProcessor,
+    pub cache: Option<&'a BuildCache>,
+    // Files of the page bundle which relative links point to
+    pub resources: &'a [PageResource],
+}
+
 #[derive(Default)]
 pub struct EmitData {
     toc: Toc,
     footnotes: Vec<String>,
+    warnings: Vec<OrgError>,
+    // Sizes from an ATTR_HTML keyword for an image link in the next paragraph
+    image_sizes: Option<String>,
+    // Whether the current link was emitted as an image
+    in_image_link: bool,
+}
+
+const IMAGE_EXTENSIONS: &[&str] = &["avif", "gif", "jpeg", "jpg", "png", "webp"];
+
+// Gets a property such as ":sizes" from an ATTR_HTML value
+fn attr_html_property(value: &str, property: &str) -> Option<String> {
+    let key = format!(":{}", property);
+
+    let words: Vec<&str> = value
+        .split_whitespace()
+        .skip_while(|word| *word != key)
+        .skip(1)
+        .take_while(|word| !word.starts_with(':'))
+        .collect();
+
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join(" "))
+    }
+}
+
+// Path from the site root of a relative link to a file of the page bundle
+fn resource_path<'a>(options: &EmitOptions<'a>, path: &str) -> Option<&'a str> {
+    let name = path.strip_prefix("file:").unwrap_or(path);
+    let name = name.strip_prefix("./").unwrap_or(name);
+
+    options
+        .resources
+        .iter()
+        .find(|resource| resource.name == name)
+        .map(|resource| resource.path.as_str())
+}
+
+// Path from the site root of an image link, relative links are images of the page bundle
+fn image_link_path<'a>(options: &EmitOptions<'a>, link: &'a Link) -> Option<&'a str> {
+    let path = resource_path(options, &link.path)
+        .unwrap_or_else(|| link.path.strip_prefix("file:").unwrap_or(&link.path));
+
+    let is_image = path
+        .rsplit('.')
+        .next()
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&&*ext.to_ascii_lowercase()));
+
+    if is_image && path.starts_with('/') {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+// Responsive image with generated variants in the srcset
+fn image_to_html(options: &EmitOptions, path: &str, sizes: &str, alt: &str) -> Result<String> {
+    let srcset = options
+        .images
+        .srcset(path)?
+        .iter()
+        .map(|variant| format!("{} {}w", variant.url, variant.width))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    Ok(format!(
+        "<img src=\"{}{}\" srcset=\"{}\" sizes=\"{}\" alt=\"{}\">",
+        options.base_url,
+        path,
+        srcset,
+        tera::escape_html(sizes),
+        tera::escape_html(alt)
+    ))
 }
 
-fn link_to_html(base_url: &str, link: &Link) -> String {
-    let (href, extra) = if link.path.starts_with("/") {
+fn link_to_html(options: &EmitOptions, link: &Link) -> String {
+    let base_url = options.base_url;
+
+    let (href, extra) = if let Some(path) = resource_path(options, &link.path) {
+        (format!("{}{}", base_url, path), "")
+    } else if link.path.starts_with("/") {
         (format!("{}{}", base_url, link.path), "")
     } else {
         (link.path.to_string(), " rel=\"noreferrer noopener\"")
@@ -31,14 +122,77 @@ fn link_to_html(base_url: &str, link: &Link) -> String {
     )
 }
 
+// Highlights code with the build cache when there is one, by inline styles or classes
+fn highlight_code(
+    options: &EmitOptions,
+    highlighting: &Highlighting,
+    language: &str,
+    code: &str,
+) -> Result<String> {
+    let syntax = highlighting.find_syntax(language)?;
+
+    let highlight = || {
+        if highlighting.classed() {
+            return Ok(highlighting.highlight_classed(syntax, code));
+        }
+
+        let mut syntax_highlighter = HighlightLines::new(syntax, highlighting.theme()?);
+        let regions = syntax_highlighter.highlight(code, highlighting.syntaxes());
+
+        Ok(styled_line_to_highlighted_html(
+            &regions[..],
+            IncludeBackground::No,
+        ))
+    };
+
+    match options.cache {
+        Some(cache) => {
+            cache.highlighted(&[&highlighting.cache_key(), &syntax.name, code], highlight)
+        }
+        None => highlight(),
+    }
+}
+
+fn is_block_element(element: &Element) -> bool {
+    !matches!(
+        element,
+        Element::Keyword(_)
+            | Element::Paragraph { .. }
+            | Element::Text { .. }
+            | Element::Link(_)
+            | Element::Bold
+            | Element::Strike
+            | Element::Italic
+            | Element::Underline
+            | Element::Verbatim { .. }
+            | Element::Code { .. }
+            | Element::Cookie(_)
+            | Element::FnRef(_)
+            | Element::InlineCall(_)
+            | Element::InlineSrc(_)
+            | Element::Macros(_)
+            | Element::RadioTarget
+            | Element::Snippet(_)
+            | Element::Target(_)
+            | Element::Timestamp(_)
+    )
+}
+
 fn emit_element_start(
     out: &mut String,
-    base_url: &str,
+    options: &EmitOptions,
     data: &mut EmitData,
     element: &Element,
-    highlighting: &Highlighting,
-    highlighting_dark: Option<&Highlighting>,
 ) -> Result<()> {
+    let highlighting = options.highlighting;
+    let highlighting_dark = options.highlighting_dark;
+
+    // ATTR_HTML sizes only apply to a link in the paragraph right after the keyword,
+    // they are dropped at the end of that paragraph or when another block starts first
+    if is_block_element(element) {
+        data.image_sizes = None;
+    }
+
     match element {
         Element::SpecialBlock(_special_block) => {}
         Element::QuoteBlock(_quote_block) => {}
@@ -62,52 +216,48 @@ fn emit_element_start(
             }
         }
         Element::SourceBlock(source_block) => {
-            if source_block.language.is_empty() {
+            let language = &*source_block.language;
+
+            // Unknown languages are rendered without highlighting instead of failing the build
+            let known_language = if language.is_empty() {
+                false
+            } else if highlighting.find_syntax(language).is_err() {
+                data.warnings
+                    .push(OrgError::UnknownSourceBlockLanguage(language.into()));
+
+                false
+            } else {
+                true
+            };
+
+            if !known_language {
                 out.push_str(&format!(
                     "<pre class=code>{}</pre>",
-                    tera::escape_html(&*source_block.contents)
+                    tera::escape_html(&source_block.contents)
                 ));
             } else {
-                fn highlight_code(
-                    highlighting: &Highlighting,
-                    language: &str,
-                    code: &str,
-                ) -> Result<String> {
-                    let syntax = highlighting.find_syntax_by_name(&language)?;
-                    let mut syntax_highlighter = HighlightLines::new(syntax, highlighting.theme()?);
-                    let regions = syntax_highlighter.highlight(code, highlighting.syntaxes());
-                    Ok(styled_line_to_highlighted_html(
-                        &regions[..],
-                        IncludeBackground::No,
-                    ))
-                }
+                let code = &*source_block.contents;
 
-                if let Some(highlighting_dark) = highlighting_dark {
+                if highlighting.classed() {
+                    // Colors come from the generated syntax stylesheets
+                    out.push_str(&format!(
+                        "<pre class=code>{}</pre>",
+                        highlight_code(options, highlighting, language, code)?
+                    ));
+                } else if let Some(highlighting_dark) = highlighting_dark {
                     // Some text browsers have dark background and does only evaluate inline styles
                     out.push_str(&format!(
                         "<pre class=\"code white\" style=display:none;>{}</pre>",
-                        highlight_code(
-                            highlighting,
-                            &*source_block.language,
-                            &*source_block.contents
-                        )?
+                        highlight_code(options, highlighting, language, code)?
                     ));
                     out.push_str(&format!(
                         "<pre class=\"code dark\">{}</pre>",
-                        highlight_code(
-                            highlighting_dark,
-                            &*source_block.language,
-                            &*source_block.contents
-                        )?
+                        highlight_code(options, highlighting_dark, language, code)?
                     ));
                 } else {
                     out.push_str(&format!(
                         "<pre class=code>{}</pre>",
-                        highlight_code(
-                            highlighting,
-                            &*source_block.language,
-                            &*source_block.contents
-                        )?
+                        highlight_code(options, highlighting, language, code)?
                     ));
                 }
             }
@@ -123,7 +273,7 @@ fn emit_element_start(
         Element::FnDef(_fn_def) => {}
         Element::FnRef(fn_ref) => {
             // Anonymous definition
-            if fn_ref.label == "" {
+            if fn_ref.label.is_empty() {
                 data.footnotes.push(
                     fn_ref
                         .definition
@@ -141,8 +291,20 @@ fn emit_element_start(
         Element::Headline { level: _ } => {}
         Element::InlineCall(_inline_call) => {}
         Element::InlineSrc(_inline_src) => {}
-        Element::Keyword(_keyword) => {}
-        Element::Link(link) => out.push_str(&link_to_html(base_url, link)),
+        Element::Keyword(keyword) => {
+            if keyword.key.eq_ignore_ascii_case("ATTR_HTML") {
+                data.image_sizes = attr_html_property(&keyword.value, "sizes");
+            }
+        }
+        Element::Link(link) => match (image_link_path(options, link), data.image_sizes.take()) {
+            (Some(path), Some(sizes)) => {
+                let alt = link.desc.clone().unwrap_or_default();
+
+                out.push_str(&image_to_html(options, path, &sizes, &alt)?);
+                data.in_image_link = true;
+            }
+            _ => out.push_str(&link_to_html(options, link)),
+        },
         Element::List(list) => {
             if list.ordered {
                 out.push_str("<ol>");
@@ -166,13 +328,13 @@ fn emit_element_start(
         Element::Code { value } => {
             out.push_str(&format!(
                 "<code class=\"code\">{}</code>",
-                tera::escape_html(&*value)
+                tera::escape_html(value)
             ));
         }
         Element::Comment(_comment) => {}
         Element::FixedWidth(_fixed_width) => {}
         Element::Title(title) => {
-            let level = title.level.min(6).max(1) as u8;
+            let level = title.level.clamp(1, 6) as u8;
 
             let text = tera::escape_html(&title.raw);
 
@@ -192,7 +354,7 @@ fn emit_element_start(
     Ok(())
 }
 
-fn emit_element_end(out: &mut String, element: &Element) {
+fn emit_element_end(out: &mut String, data: &mut EmitData, element: &Element) {
     match element {
         Element::SpecialBlock(_special_block) => {}
         Element::QuoteBlock(_quote_block) => {}
@@ -216,7 +378,13 @@ fn emit_element_end(out: &mut String, element: &Element) {
         Element::InlineCall(_inline_call) => {}
         Element::InlineSrc(_inline_src) => {}
         Element::Keyword(_keyword) => {}
-        Element::Link(_link) => out.push_str("</a>"),
+        Element::Link(_link) => {
+            if data.in_image_link {
+                data.in_image_link = false;
+            } else {
+                out.push_str("</a>");
+            }
+        }
         Element::List(list) => {
             if list.ordered {
                 out.push_str("</ol>");
@@ -227,7 +395,10 @@ fn emit_element_end(out: &mut String, element: &Element) {
         Element::ListItem(_list_item) => out.push_str("</li>"),
         Element::Macros(_macros) => {}
         Element::Snippet(_snippet) => {}
-        Element::Paragraph { post_blank: _ } => out.push_str("</p>"),
+        Element::Paragraph { post_blank: _ } => {
+            data.image_sizes = None;
+            out.push_str("</p>");
+        }
         Element::Rule(_rule) => {}
         Element::Timestamp(_timestamp) => {}
         Element::Target(_target) => {}
@@ -239,7 +410,7 @@ fn emit_element_end(out: &mut String, element: &Element) {
         Element::Code { value: _ } => {}
         Element::FixedWidth(_fixed_width) => {}
         Element::Title(title) => {
-            let level = title.level.min(6).max(1) as u8;
+            let level = title.level.clamp(1, 6) as u8;
 
             out.push_str(&format!("</a></h{}>", level));
         }
@@ -252,25 +423,16 @@ fn emit_element_end(out: &mut String, element: &Element) {
 
 pub fn emit_document(
     document: &Org,
-    base_url: &str,
-    highlighting: &Highlighting,
-    highlighting_dark: Option<&Highlighting>,
-) -> Result<(Toc, String)> {
+    options: &EmitOptions,
+) -> Result<(Toc, String, Vec<OrgError>)> {
     let mut out = String::with_capacity(1024);
 
     let mut data = EmitData::default();
 
     for event in document.iter() {
         match event {
-            Event::Start(element) => emit_element_start(
-                &mut out,
-                base_url,
-                &mut data,
-                element,
-                highlighting,
-                highlighting_dark,
-            )?,
-            Event::End(element) => emit_element_end(&mut out, element),
+            Event::Start(element) => emit_element_start(&mut out, options, &mut data, element)?,
+            Event::End(element) => emit_element_end(&mut out, &mut data, element),
         }
     }
 
@@ -287,7 +449,7 @@ pub fn emit_document(
             for event in org.iter() {
                 match event {
                     Event::Start(element) => match element {
-                        Element::Link(link) => out.push_str(&link_to_html(base_url, link)),
+                        Element::Link(link) => out.push_str(&link_to_html(options, link)),
                         Element::Text { value } => out.push_str(&tera::escape_html(value)),
                         Element::Bold => out.push_str("<i>"),
                         Element::Strike => out.push_str("<s>"),
@@ -312,7 +474,7 @@ pub fn emit_document(
         out.push_str("</ol></section>");
     }
 
-    Ok((data.toc, out))
+    Ok((data.toc, out, data.warnings))
 }
 
 #[derive(Error, Debug)]
@@ -320,3 +482,101 @@ pub enum OrgError {
     #[error("unknown source block language \"{0}\"")]
     UnknownSourceBlockLanguage(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SiteImagesConfig;
+    use image::RgbImage;
+    use std::{fs, path::PathBuf};
+
+    fn emit(text: &str, resources: &[PageResource]) -> String {
+        emit_with_warnings(text, resources).0
+    }
+
+    fn emit_with_warnings(text: &str, resources: &[PageResource]) -> (String, Vec<OrgError>) {
+        let root = tempfile::tempdir().unwrap();
+        let out_path = root.path().join("out");
+        fs::create_dir_all(out_path.join("posts/trip")).unwrap();
+        RgbImage::new(200, 100)
+            .save(out_path.join("a.png"))
+            .unwrap();
+        RgbImage::new(200, 100)
+            .save(out_path.join("posts/trip/map.png"))
+            .unwrap();
+
+        let config = SiteImagesConfig {
+            srcset_widths: vec![100],
+            format: None,
+        };
+        let images = ImageProcessor::new(root.path(), &out_path, "", config, Default::default());
+        let options = EmitOptions {
+            base_url: "",
+            highlighting: &Highlighting::new(root.path(), None).unwrap(),
+            highlighting_dark: None,
+            images: &images,
+            cache: None,
+            resources,
+        };
+
+        let (_, html, warnings) = emit_document(&Org::parse(text), &options).unwrap();
+
+        (html, warnings)
+    }
+
+    #[test]
+    fn unknown_languages_are_not_highlighted() {
+        let (html, warnings) = emit_with_warnings(
+            "#+BEGIN_SRC nope\n<b>code</b>\n#+END_SRC\n#+BEGIN_SRC rs\nfn main() {}\n#+END_SRC\n",
+            &[],
+        );
+
+        assert!(
+            html.contains("<pre class=code>&lt;b&gt;code&lt;&#x2F;b&gt;\n</pre>"),
+            "{}",
+            html
+        );
+        assert!(html.contains("style="), "{}", html);
+        assert_eq!(
+            warnings.iter().map(|w| w.to_string()).collect::<Vec<_>>(),
+            ["unknown source block language \"nope\""]
+        );
+    }
+
+    #[test]
+    fn image_sizes_apply_to_a_link_after_text() {
+        let html = emit("#+ATTR_HTML: :sizes 50vw\nSee [[file:/a.png]] here.\n", &[]);
+
+        assert!(html.contains("srcset=\"/processed_images/a."), "{}", html);
+        assert!(html.contains(" 100w\" sizes=\"50vw\""), "{}", html);
+    }
+
+    #[test]
+    fn image_sizes_end_with_the_paragraph() {
+        let html = emit(
+            "#+ATTR_HTML: :sizes 50vw\nText.\n\nSee [[file:/a.png]].\n",
+            &[],
+        );
+
+        assert!(!html.contains("srcset"), "{}", html);
+    }
+
+    #[test]
+    fn image_sizes_for_bundle_images() {
+        let resources = [PageResource {
+            name: "map.png".into(),
+            path: "/posts/trip/map.png".into(),
+            source_path: PathBuf::new(),
+        }];
+        let html = emit(
+            "#+ATTR_HTML: :sizes 100vw\n[[file:./map.png]]\n",
+            &resources,
+        );
+
+        assert!(
+            html.contains("<img src=\"/posts/trip/map.png\" srcset=\"/processed_images/map."),
+            "{}",
+            html
+        );
+    }
+}
@@ -1,17 +1,38 @@
-use crate::{config::SiteConfig, highlighting::Highlighting, org_emitter::emit_document, toc::Toc};
+use crate::{
+    cache::{hash_parts, hash_value, BuildCache},
+    config::SiteConfig,
+    diagnostics::{Diagnostic, Diagnostics},
+    org_emitter::{emit_document, EmitOptions, OrgError},
+    search::extract_document_text,
+    taxonomies::{is_valid_slug, same_term, split_terms, term_slug, TaxonomyError, COLLISION_HELP},
+    toc::Toc,
+};
 use anyhow::Result;
 use chrono::NaiveDate;
 use orgize::Org;
-use serde_derive::Serialize;
+use serde_derive::{Deserialize, Serialize};
 use std::{
+    cell::OnceCell,
+    collections::BTreeMap,
     fs,
     path::{Path, PathBuf},
 };
 use thiserror::Error;
 
-#[derive(Serialize)]
-pub struct Page {
-    document: Org<'static>,
+// A file next to the index.org of a page bundle
+#[derive(Clone, Debug, Serialize)]
+pub struct PageResource {
+    // Path relative to the bundle dir, such as "images/diagram.png"
+    pub name: String,
+    // Path the file is written to, such as "/posts/my-post/images/diagram.png"
+    pub path: String,
+    #[serde(skip)]
+    pub source_path: PathBuf,
+}
+
+// Keywords of a page, cached by the hash of its org text and the config
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct PageData {
     title: Option<String>,
     ty: Option<String>,
     template: Option<String>,
@@ -20,6 +41,167 @@ pub struct Page {
     date: Option<NaiveDate>,
     date_updated: Option<NaiveDate>,
     tags: Vec<String>,
+    // Terms of every taxonomy including the tags, keyed by the taxonomy name
+    taxonomies: BTreeMap<String, Vec<String>>,
+    // Old paths which redirect to the page
+    aliases: Vec<String>,
+    series: Option<String>,
+    series_part: Option<u32>,
+    // Read from an _index.org, the page of the section of its directory
+    is_section: bool,
+    // Position in sections sorted by weight, lower first
+    weight: Option<i64>,
+    // Order of the pages of a section, date, title or weight
+    sort_by: Option<String>,
+}
+
+// Text extracted from the document, so a cached page is not parsed again
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct PageText {
+    // Bytes of the org text up to a `# more` line or the first words
+    summary_len: usize,
+    // Whether the document goes on after the summary
+    summary_truncated: bool,
+    // Plain text of the summary cut at the word count
+    summary: String,
+    headings: Vec<String>,
+    body: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedPage {
+    data: PageData,
+    text: PageText,
+}
+
+#[derive(Serialize)]
+pub struct Page {
+    // Parsed when first needed, templates get the rendered document instead
+    #[serde(skip)]
+    document: OnceCell<Org<'static>>,
+    // The org text the document is parsed from
+    #[serde(skip)]
+    source: &'static str,
+    // Path from the file under the content dir, which decides the section of the page
+    #[serde(skip)]
+    file_path: PathBuf,
+    // The org file the page was read from
+    #[serde(skip)]
+    source_path: Option<PathBuf>,
+    #[serde(flatten)]
+    data: PageData,
+    // Files of a page bundle, written next to the page
+    resources: Vec<PageResource>,
+    #[serde(skip)]
+    text: PageText,
+}
+
+// Parsed pages of earlier builds
+pub struct PageCache<'a> {
+    cache: &'a BuildCache,
+    config_hash: String,
+}
+
+impl<'a> PageCache<'a> {
+    pub fn new(cache: &'a BuildCache, config: &SiteConfig) -> Result<PageCache<'a>> {
+        Ok(PageCache {
+            cache,
+            config_hash: hash_value(config)?,
+        })
+    }
+}
+
+// Plain text of the first words, marking where it was cut
+fn cut_words(text: &str, words: usize) -> String {
+    let text_words: Vec<&str> = text.split_whitespace().collect();
+
+    if text_words.len() > words {
+        format!("{}…", text_words[..words].join(" "))
+    } else {
+        text_words.join(" ")
+    }
+}
+
+// Splits the org text at a `# more` line, or after the paragraph reaching the word count
+fn split_summary(source: &str, words: usize) -> (&str, bool) {
+    let mut offset = 0;
+    let mut count = 0;
+    let mut in_block = false;
+
+    for line in source.split_inclusive('\n') {
+        let trimmed = line.trim();
+        let upper = trimmed.to_ascii_uppercase();
+
+        if upper.starts_with("#+BEGIN") {
+            in_block = true;
+        } else if upper.starts_with("#+END") {
+            in_block = false;
+        } else if in_block {
+            // Lines in blocks are neither counted nor markers
+        } else if trimmed.eq_ignore_ascii_case("# more") {
+            return (&source[..offset], true);
+        } else if trimmed.is_empty() && count >= words {
+            return (&source[..offset], !source[offset..].trim().is_empty());
+        } else if !trimmed.starts_with('#') {
+            count += trimmed.split_whitespace().count();
+        }
+
+        offset += line.len();
+    }
+
+    (source, false)
+}
+
+// Files of a page bundle, directories with org files belong to other pages
+fn collect_resource_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut subdirs = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        // Hidden files such as editor backups are left out
+        if path.file_name().unwrap().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        if path.is_dir() {
+            subdirs.push(path);
+        } else if path.extension().is_none_or(|ext| ext != "org") {
+            files.push(path);
+        }
+    }
+
+    for subdir in subdirs {
+        let has_pages = fs::read_dir(&subdir)?
+            .filter_map(|entry| entry.ok())
+            .any(|entry| entry.path().extension().is_some_and(|ext| ext == "org"));
+
+        if !has_pages {
+            collect_resource_files(&subdir, files)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Parses a series such as "Writing a parser" or "Writing a parser #2",
+// a "#" without digits after it is part of the name as in "C# tips"
+fn parse_series(value: &str) -> Result<(Option<String>, Option<u32>), PageError> {
+    let value = value.trim();
+
+    let part = value.rsplit_once('#').filter(|(_, part)| {
+        let part = part.trim();
+        !part.is_empty() && part.chars().all(|c| c.is_ascii_digit())
+    });
+
+    match part {
+        Some((name, part)) => match part.trim().parse::<u32>() {
+            Ok(part) if !name.trim().is_empty() => Ok((Some(name.trim().into()), Some(part))),
+            _ => Err(PageError::InvalidSeries(value.into())),
+        },
+        None if !value.is_empty() => Ok((Some(value.into()), None)),
+        None => Ok((None, None)),
+    }
 }
 
 fn get_keyword(document: &Org, keyword: &str) -> Option<String> {
@@ -32,79 +214,438 @@ fn get_keyword(document: &Org, keyword: &str) -> Option<String> {
     None
 }
 
+const TERMS_HELP: &str = "terms are separated by commas, or by spaces without commas, \
+    and need a letter or digit; verbatim slugs may only add `-`, `_`, `.` and `~`";
+
+const DATE_HELP: &str =
+    "dates look like <2021-01-31 Sun>, optionally followed by ---<2021-02-01 Mon>";
+
+// Parses a date field such as "<2021-01-01 Fri>" or "<2021-01-01 Fri>---<2021-02-01 Mon>"
+fn parse_date_field(value: &str) -> Result<(NaiveDate, Option<NaiveDate>), PageError> {
+    let parse_date = |date: &str| {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d %a")
+            .map_err(|_| PageError::InvalidDateField("Invalid date".into(), value.to_string()))
+    };
+
+    if !value.starts_with('<') {
+        return Err(PageError::InvalidDateField(
+            "Missing timestamp".into(),
+            value.to_string(),
+        ));
+    }
+
+    let end = value.find('>').ok_or_else(|| {
+        PageError::InvalidDateField("Unterminated timestamp".into(), value.to_string())
+    })?;
+
+    let date = parse_date(&value[1..end])?;
+
+    let rest = &value[(end + 1)..];
+
+    let mut edited = None;
+
+    if !rest.is_empty() {
+        if !rest.starts_with("---") {
+            return Err(PageError::InvalidDateField(
+                "Wrong sequence between dates".into(),
+                value.to_string(),
+            ));
+        }
+
+        let rest = &value[(end + 4)..];
+
+        if !rest.starts_with('<') {
+            return Err(PageError::InvalidDateField(
+                "Missing timestamp".into(),
+                value.to_string(),
+            ));
+        }
+
+        let end = rest.find('>').ok_or_else(|| {
+            PageError::InvalidDateField("Unterminated timestamp".into(), value.to_string())
+        })?;
+
+        edited = Some(parse_date(&rest[1..end])?);
+    }
+
+    Ok((date, edited))
+}
+
+// Byte offset of a needle in the value of a keyword line such as "#+TAGS: a b"
+fn keyword_offset(text: &str, keyword: &str, needle: &str) -> Option<usize> {
+    let prefix = format!("#+{}:", keyword.to_ascii_lowercase());
+    let mut offset = 0;
+
+    for line in text.split('\n') {
+        let trimmed = line.trim_start();
+
+        if trimmed.to_ascii_lowercase().starts_with(&prefix) {
+            let value_start = offset + (line.len() - trimmed.len()) + prefix.len();
+
+            return text[value_start..offset + line.len()]
+                .find(needle)
+                .map(|i| value_start + i);
+        }
+
+        offset += line.len() + 1;
+    }
+
+    None
+}
+
+// Byte offset of the language of the first source block using it
+fn source_block_offset(text: &str, language: &str) -> Option<usize> {
+    let mut offset = 0;
+
+    for line in text.split('\n') {
+        let trimmed = line.trim_start();
+
+        if trimmed.to_ascii_lowercase().starts_with("#+begin_src") {
+            let args_start = offset + (line.len() - trimmed.len()) + "#+begin_src".len();
+            let args = &text[args_start..offset + line.len()];
+
+            if args.split_whitespace().next() == Some(language) {
+                return args.find(language).map(|i| args_start + i);
+            }
+        }
+
+        offset += line.len() + 1;
+    }
+
+    None
+}
+
+// Points at the needle in the keyword line when it can be found
+fn keyword_diagnostic(
+    file: &Path,
+    source: &str,
+    message: String,
+    keyword: &str,
+    needle: &str,
+) -> Diagnostic {
+    let diagnostic = Diagnostic::error(message, file);
+
+    match keyword_offset(source, keyword, needle) {
+        Some(offset) => diagnostic.at(source, offset, needle.chars().count()),
+        None => diagnostic,
+    }
+}
+
+// Paths from the site root without a trailing slash, such as "/posts/hello"
+pub(crate) fn normalize_page_path(path: &str) -> PathBuf {
+    Path::new("/").join(path.trim_matches('/'))
+}
+
+// Fills a section pattern such as "/posts/:year/:month/:slug/", placeholders are whole segments
+fn fill_permalink_pattern(
+    pattern: &str,
+    section: &str,
+    slug: &str,
+    date: Option<&NaiveDate>,
+) -> Result<String, PageError> {
+    let mut segments = Vec::new();
+
+    for segment in pattern.split('/') {
+        let date_part = |format: &str| {
+            date.map(|date| date.format(format).to_string())
+                .ok_or_else(|| PageError::PermalinkNeedsDate(pattern.into()))
+        };
+
+        segments.push(match segment {
+            ":year" => date_part("%Y")?,
+            ":month" => date_part("%m")?,
+            ":day" => date_part("%d")?,
+            ":slug" => slug.into(),
+            ":section" => section.into(),
+            _ if segment.starts_with(':') => {
+                return Err(PageError::UnknownPermalinkPlaceholder(segment.into()))
+            }
+            _ => segment.into(),
+        });
+    }
+
+    Ok(segments.join("/"))
+}
+
 impl Page {
-    pub fn parse(page_path: PathBuf, text: String, _config: &SiteConfig) -> Result<Page> {
-        let document = Org::parse(Box::leak(text.into_boxed_str()));
+    pub fn parse(page_path: PathBuf, text: String, config: &SiteConfig) -> Result<Page> {
+        Page::parse_source(page_path, false, None, text, config, None)
+    }
+
+    // The file path decides the section of the page, the page path is where it is written to
+    fn parse_source(
+        file_path: PathBuf,
+        is_index: bool,
+        source_path: Option<PathBuf>,
+        text: String,
+        config: &SiteConfig,
+        cache: Option<&PageCache>,
+    ) -> Result<Page> {
+        let source: &'static str = Box::leak(text.into_boxed_str());
+
+        let cache_key = cache.map(|cache| {
+            hash_parts(&[
+                env!("CARGO_PKG_VERSION"),
+                &cache.config_hash,
+                &file_path.to_string_lossy(),
+                &is_index.to_string(),
+                source,
+            ])
+        });
+
+        if let (Some(cache), Some(cache_key)) = (cache, &cache_key) {
+            if let Some(cached) = cache.cache.memoized::<CachedPage>("pages", cache_key) {
+                return Ok(Page {
+                    document: OnceCell::new(),
+                    source,
+                    file_path,
+                    source_path,
+                    data: cached.data,
+                    resources: Vec::new(),
+                    text: cached.text,
+                });
+            }
+        }
+
+        let document = Org::parse(source);
+
+        let file = source_path.clone().unwrap_or_else(|| file_path.clone());
+        let mut diagnostics = Vec::new();
+
+        let keyword_diagnostic = |message: String, keyword: &str, needle: &str| {
+            keyword_diagnostic(&file, source, message, keyword, needle)
+        };
 
         let title = get_keyword(&document, "TITLE").map(|t| t.to_string());
         let ty = get_keyword(&document, "TYPE").map(|t| t.to_string());
         let template = get_keyword(&document, "TEMPLATE").map(|t| t.to_string());
         let description = get_keyword(&document, "DESCRIPTION").map(|t| t.to_string());
 
-        let (date, date_updated) = if let Some(value) = get_keyword(&document, "DATE") {
-            if !value.starts_with('<') {
-                return Err(PageError::InvalidDateField(
-                    "Missing timestamp".into(),
-                    value.to_string(),
-                )
-                .into());
+        let (date, date_updated) = match get_keyword(&document, "DATE") {
+            Some(value) => match parse_date_field(&value) {
+                Ok((date, edited)) => (Some(date), edited),
+                Err(err) => {
+                    diagnostics.push(
+                        keyword_diagnostic(err.to_string(), "DATE", value.trim())
+                            .with_help(DATE_HELP),
+                    );
+
+                    (None, None)
+                }
+            },
+            None => (None, None),
+        };
+
+        let mut taxonomies = BTreeMap::new();
+
+        for taxonomy in config.taxonomies() {
+            let keyword = taxonomy.keyword();
+            let mut terms: Vec<String> = Vec::new();
+            let mut slugs: Vec<String> = Vec::new();
+
+            if let Some(terms_value) = get_keyword(&document, &keyword) {
+                for term in split_terms(&terms_value) {
+                    let slug = term_slug(term, taxonomy.slug);
+
+                    if !is_valid_slug(&slug) {
+                        diagnostics.push(
+                            keyword_diagnostic(
+                                PageError::InvalidTerm(taxonomy.name.clone(), term.into())
+                                    .to_string(),
+                                &keyword,
+                                term,
+                            )
+                            .with_help(TERMS_HELP),
+                        );
+
+                        continue;
+                    }
+
+                    // Terms with the same slug are the same term, the first spelling is kept
+                    match slugs.iter().position(|other| *other == slug) {
+                        Some(index) if !same_term(&terms[index], term) => {
+                            diagnostics.push(
+                                keyword_diagnostic(
+                                    TaxonomyError::SlugCollision(
+                                        term.into(),
+                                        terms[index].clone(),
+                                        slug,
+                                    )
+                                    .to_string(),
+                                    &keyword,
+                                    term,
+                                )
+                                .with_help(COLLISION_HELP),
+                            );
+                        }
+                        Some(_) => {}
+                        None => {
+                            slugs.push(slug);
+                            terms.push(term.into());
+                        }
+                    }
+                }
             }
 
-            let end = value.find('>').ok_or_else(|| {
-                PageError::InvalidDateField("Unterminated timestamp".into(), value.to_string())
-            })?;
+            taxonomies.insert(taxonomy.name, terms);
+        }
 
-            let date = NaiveDate::parse_from_str(&value[1..end], "%Y-%m-%d %a")?;
+        let tags = taxonomies.get("tags").cloned().unwrap_or_default();
 
-            let rest = &value[(end + 1)..];
+        let mut aliases = Vec::new();
 
-            let mut edited = None;
+        if let Some(aliases_value) = get_keyword(&document, "ALIASES") {
+            for alias in aliases_value.split_whitespace() {
+                let trimmed = alias.trim_end_matches('/');
 
-            if !rest.is_empty() {
-                if !rest.starts_with("---") {
-                    return Err(PageError::InvalidDateField(
-                        "Wrong sequence between dates".into(),
-                        value.to_string(),
-                    )
-                    .into());
+                if !alias.starts_with('/') || trimmed.is_empty() {
+                    diagnostics.push(
+                        keyword_diagnostic(
+                            PageError::InvalidAlias(alias.into()).to_string(),
+                            "ALIASES",
+                            alias,
+                        )
+                        .with_help("aliases are paths from the site root such as /posts/old-name"),
+                    );
+
+                    continue;
                 }
 
-                let rest = &value[(end + 4)..];
+                aliases.push(trimmed.into());
+            }
+        }
 
-                if !rest.starts_with('<') {
-                    return Err(PageError::InvalidDateField(
-                        "Missing timestamp".into(),
-                        value.to_string(),
-                    )
-                    .into());
+        // A series name with an optional part number such as "Writing a parser #2"
+        let mut series = None;
+        let mut series_part = None;
+
+        if let Some(series_value) = get_keyword(&document, "SERIES") {
+            match parse_series(&series_value) {
+                Ok((name, part)) => {
+                    series = name;
+                    series_part = part;
                 }
+                Err(error) => diagnostics.push(
+                    keyword_diagnostic(error.to_string(), "SERIES", series_value.trim()).with_help(
+                        "series look like \"Writing a parser\" or \"Writing a parser #2\"",
+                    ),
+                ),
+            }
+        }
 
-                let end = rest.find('>').ok_or_else(|| {
-                    PageError::InvalidDateField("Unterminated timestamp".into(), value.to_string())
-                })?;
+        let weight = match get_keyword(&document, "WEIGHT") {
+            Some(value) => match value.trim().parse() {
+                Ok(weight) => Some(weight),
+                Err(_) => {
+                    diagnostics.push(
+                        keyword_diagnostic(
+                            PageError::InvalidWeight(value.trim().into()).to_string(),
+                            "WEIGHT",
+                            value.trim(),
+                        )
+                        .with_help("weights are whole numbers such as 10 or -1"),
+                    );
 
-                edited = Some(NaiveDate::parse_from_str(&rest[1..end], "%Y-%m-%d %a")?);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let sort_by = match get_keyword(&document, "SORT_BY") {
+            Some(value) if ["date", "title", "weight"].contains(&value.trim()) => {
+                Some(value.trim().to_string())
             }
+            Some(value) => {
+                diagnostics.push(
+                    keyword_diagnostic(
+                        PageError::InvalidSortBy(value.trim().into()).to_string(),
+                        "SORT_BY",
+                        value.trim(),
+                    )
+                    .with_help("sections are sorted by date, title or weight"),
+                );
 
-            (Some(date), edited)
-        } else {
-            (None, None)
+                None
+            }
+            None => None,
         };
 
-        let mut tags = Vec::new();
+        let slug = get_keyword(&document, "SLUG").map(|s| s.trim().to_string());
+        let permalink = get_keyword(&document, "PERMALINK").map(|p| p.trim().to_string());
+
+        let mut page_path = file_path.clone();
+
+        if let Some(permalink) = &permalink {
+            if permalink.starts_with('/') && !permalink.split('/').any(|part| part == "..") {
+                page_path = normalize_page_path(permalink);
+            } else {
+                diagnostics.push(
+                    keyword_diagnostic(
+                        PageError::InvalidPermalink(permalink.clone()).to_string(),
+                        "PERMALINK",
+                        permalink,
+                    )
+                    .with_help("permalinks are paths from the site root such as /about/"),
+                );
+            }
+        } else if !is_index {
+            let section = file_path
+                .parent()
+                .map(|parent| parent.to_string_lossy().trim_matches('/').to_string())
+                .unwrap_or_default();
+
+            let file_slug = file_path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+
+            let page_slug = match &slug {
+                Some(slug) if is_valid_slug(slug) => slug.clone(),
+                Some(slug) => {
+                    diagnostics.push(
+                        keyword_diagnostic(
+                            PageError::InvalidSlug(slug.clone()).to_string(),
+                            "SLUG",
+                            slug,
+                        )
+                        .with_help("slugs are a single path segment such as my-first-post"),
+                    );
 
-        if let Some(tags_value) = get_keyword(&document, "TAGS") {
-            for tag in tags_value.split(' ') {
-                if tag.chars().any(|c| !c.is_ascii_lowercase()) {
-                    return Err(PageError::InvalidTag(tag.into()).into());
+                    file_slug
                 }
+                None => file_slug,
+            };
 
-                tags.push(tag.into());
+            match config.permalinks.get(&section) {
+                Some(pattern) => {
+                    match fill_permalink_pattern(pattern, &section, &page_slug, date.as_ref()) {
+                        Ok(path) => page_path = normalize_page_path(&path),
+                        Err(err) => diagnostics.push(
+                            Diagnostic::error(err.to_string(), &file).with_help(format!(
+                                "the pattern is set for \"{}\" in [permalinks] of Nuko.toml",
+                                section
+                            )),
+                        ),
+                    }
+                }
+                None => page_path = normalize_page_path(&format!("{}/{}", section, page_slug)),
             }
         }
 
-        Ok(Page {
-            document,
+        if !diagnostics.is_empty() {
+            return Err(Diagnostics(diagnostics).into());
+        }
+
+        let (summary_source, summary_truncated) = split_summary(source, config.summary.words);
+        let (_, summary_body) = extract_document_text(&Org::parse(summary_source));
+        let (headings, body) = extract_document_text(&document);
+        let summary = cut_words(&summary_body, config.summary.words);
+
+        // Pages without a description are described by the start of their text
+        let description = description.or_else(|| Some(summary.clone()).filter(|s| !s.is_empty()));
+
+        let data = PageData {
             title,
             ty,
             template,
@@ -113,22 +654,63 @@ impl Page {
             date,
             date_updated,
             tags,
+            taxonomies,
+            aliases,
+            series,
+            series_part,
+            is_section: is_index,
+            weight,
+            sort_by,
+        };
+
+        let text = PageText {
+            summary_len: summary_source.len(),
+            summary_truncated,
+            summary,
+            headings,
+            body,
+        };
+
+        if let (Some(cache), Some(cache_key)) = (cache, &cache_key) {
+            cache.cache.memoize(
+                "pages",
+                cache_key,
+                &CachedPage {
+                    data: data.clone(),
+                    text: text.clone(),
+                },
+            )?;
+        }
+
+        Ok(Page {
+            document: OnceCell::from(document),
+            source,
+            file_path,
+            source_path,
+            data,
+            resources: Vec::new(),
+            text,
         })
     }
 
-    pub fn read_file(root_path: &Path, path: PathBuf, config: &SiteConfig) -> Result<Page> {
+    pub fn read_file(
+        root_path: &Path,
+        path: PathBuf,
+        config: &SiteConfig,
+        cache: Option<&PageCache>,
+    ) -> Result<Page> {
         let text = fs::read_to_string(&path)?;
 
         let content_path = root_path.join("content");
 
-        let is_root = path
-            .file_name()
-            .unwrap()
-            .to_string_lossy()
-            .starts_with("_index");
+        let file_name = path.file_name().unwrap().to_string_lossy();
+        let is_root = file_name.starts_with("_index");
+        // An index.org is a page bundle, the page of its directory with the files next to it,
+        // except in the content dir where it would take the files of every other directory
+        let is_bundle = file_name == "index.org" && path.parent() != Some(content_path.as_path());
 
         // Create absolute page paths
-        let page_path = if is_root {
+        let page_path = if is_root || is_bundle {
             Path::new("/").join(
                 path.strip_prefix(&content_path)
                     .unwrap()
@@ -139,52 +721,222 @@ impl Page {
             Path::new("/").join(path.strip_prefix(&content_path).unwrap().with_extension(""))
         };
 
-        Page::parse(page_path, text, config)
+        let bundle_path = path.parent().map(|p| p.to_path_buf());
+
+        let mut page = Page::parse_source(page_path, is_root, Some(path), text, config, cache)?;
+
+        if let (true, Some(bundle_path)) = (is_bundle, bundle_path) {
+            page.resources = page.find_resources(&bundle_path)?;
+        }
+
+        Ok(page)
+    }
+
+    fn find_resources(&self, bundle_path: &Path) -> Result<Vec<PageResource>> {
+        let page_path = self.data.page_path.to_string_lossy();
+        let page_path = page_path.trim_end_matches('/');
+        let mut files = Vec::new();
+
+        collect_resource_files(bundle_path, &mut files)?;
+
+        let mut resources = Vec::new();
+
+        for file in files {
+            let name = file
+                .strip_prefix(bundle_path)?
+                .components()
+                .map(|part| part.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            // The page itself is written to index.html
+            if name == "index.html" {
+                return Err(Diagnostics(vec![Diagnostic::error(
+                    PageError::ReservedResource(name).to_string(),
+                    &file,
+                )
+                .with_help("rename the file, bundles cannot contain an index.html")])
+                .into());
+            }
+
+            resources.push(PageResource {
+                path: format!("{}/{}", page_path, name),
+                name,
+                source_path: file,
+            });
+        }
+
+        resources.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(resources)
+    }
+
+    pub fn render_html(&self, options: &EmitOptions) -> Result<(Toc, String, Vec<OrgError>)> {
+        emit_document(self.document(), options)
+    }
+
+    // The summary rendered on its own, warnings are reported for the whole document
+    pub fn render_summary_html(&self, options: &EmitOptions) -> Result<String> {
+        let summary_source = &self.source[..self.text.summary_len];
+        let (_, html, _) = emit_document(&Org::parse(summary_source), options)?;
+
+        Ok(html)
+    }
+
+    pub fn summary_truncated(&self) -> bool {
+        self.text.summary_truncated
+    }
+
+    // Headings and the text outside of them, without markup
+    pub fn headings(&self) -> &[String] {
+        &self.text.headings
+    }
+
+    pub fn body_text(&self) -> &str {
+        &self.text.body
+    }
+
+    pub fn source(&self) -> &str {
+        self.source
+    }
+
+    // Files named by #+INCLUDE keywords, relative to the source file of the page
+    pub fn includes(&self) -> Vec<PathBuf> {
+        let dir = match self.source_path.as_ref().and_then(|path| path.parent()) {
+            Some(dir) => dir,
+            None => return Vec::new(),
+        };
+
+        self.document()
+            .keywords()
+            .filter(|keyword| keyword.key.eq_ignore_ascii_case("INCLUDE"))
+            .filter_map(|keyword| {
+                let value = keyword.value.trim();
+
+                let file = match value.strip_prefix('"') {
+                    Some(quoted) => quoted.split('"').next(),
+                    None => value.split_whitespace().next(),
+                }?;
+
+                Some(dir.join(file)).filter(|_| !file.is_empty())
+            })
+            .collect()
+    }
+
+    // File name used in diagnostics
+    pub fn diagnostic_file(&self) -> &Path {
+        self.source_path.as_deref().unwrap_or(&self.data.page_path)
     }
 
-    pub fn render_html(
-        &self,
-        base_url: &str,
-        highlighting: &Highlighting,
-        highlighting_dark: Option<&Highlighting>,
-    ) -> Result<(Toc, String)> {
-        emit_document(&self.document, base_url, highlighting, highlighting_dark)
+    // Locates a warning from rendering the document in the source
+    pub fn warning_diagnostic(&self, warning: &OrgError) -> Diagnostic {
+        let diagnostic = Diagnostic::warning(warning.to_string(), self.diagnostic_file());
+
+        match warning {
+            OrgError::UnknownSourceBlockLanguage(language) => {
+                match source_block_offset(self.source, language) {
+                    Some(offset) => diagnostic
+                        .at(self.source, offset, language.chars().count())
+                        .with_help("add an alias in [syntax_aliases] or see `nuko syntaxes`"),
+                    None => diagnostic,
+                }
+            }
+        }
     }
 
-    pub fn document(&self) -> &Org {
-        &self.document
+    pub fn document(&self) -> &Org<'_> {
+        self.document.get_or_init(|| Org::parse(self.source))
     }
 
     pub fn title(&self) -> Option<&str> {
-        self.title.as_ref().map(|s| s.as_str())
+        self.data.title.as_deref()
     }
 
     pub fn description(&self) -> Option<&str> {
-        self.description.as_ref().map(|s| s.as_str())
+        self.data.description.as_deref()
     }
 
     pub fn ty(&self) -> Option<&str> {
-        self.ty.as_ref().map(|s| s.as_str())
+        self.data.ty.as_deref()
     }
 
     pub fn template(&self) -> Option<&str> {
-        self.template.as_ref().map(|s| s.as_str())
+        self.data.template.as_deref()
     }
 
     pub fn date(&self) -> Option<&NaiveDate> {
-        self.date.as_ref()
+        self.data.date.as_ref()
     }
 
     pub fn date_updated(&self) -> Option<&NaiveDate> {
-        self.date_updated.as_ref()
+        self.data.date_updated.as_ref()
     }
 
     pub fn page_path(&self) -> &Path {
-        &self.page_path
+        &self.data.page_path
+    }
+
+    pub fn file_path(&self) -> &Path {
+        &self.file_path
+    }
+
+    pub fn source_path(&self) -> Option<&Path> {
+        self.source_path.as_deref()
     }
 
     pub fn tags(&self) -> &[String] {
-        &self.tags
+        &self.data.tags
+    }
+
+    pub fn terms(&self, taxonomy: &str) -> &[String] {
+        self.data
+            .taxonomies
+            .get(taxonomy)
+            .map(|terms| terms.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn resources(&self) -> &[PageResource] {
+        &self.resources
+    }
+
+    pub fn taxonomies(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.data.taxonomies
+    }
+
+    pub fn aliases(&self) -> &[String] {
+        &self.data.aliases
+    }
+
+    pub fn series(&self) -> Option<&str> {
+        self.data.series.as_deref()
+    }
+
+    pub fn series_part(&self) -> Option<u32> {
+        self.data.series_part
+    }
+
+    pub fn is_section(&self) -> bool {
+        self.data.is_section
+    }
+
+    pub fn weight(&self) -> Option<i64> {
+        self.data.weight
+    }
+
+    pub fn sort_by(&self) -> Option<&str> {
+        self.data.sort_by.as_deref()
+    }
+
+    // Points at a keyword of the page, such as an alias colliding with another page
+    pub fn keyword_diagnostic(&self, message: String, keyword: &str, needle: &str) -> Diagnostic {
+        keyword_diagnostic(
+            self.diagnostic_file(),
+            self.source,
+            message,
+            keyword,
+            needle,
+        )
     }
 }
 
@@ -192,6 +944,255 @@ impl Page {
 pub enum PageError {
     #[error("invalid date field: {0} \"{1}\"")]
     InvalidDateField(String, String),
-    #[error("invalid tag: \"{0}\"")]
-    InvalidTag(String),
+    #[error("page bundle resource \"{0}\" would overwrite the page")]
+    ReservedResource(String),
+    #[error("invalid {0} term: \"{1}\"")]
+    InvalidTerm(String, String),
+    #[error("invalid alias: \"{0}\"")]
+    InvalidAlias(String),
+    #[error("invalid series: \"{0}\"")]
+    InvalidSeries(String),
+    #[error("invalid weight: \"{0}\"")]
+    InvalidWeight(String),
+    #[error("invalid sort order: \"{0}\"")]
+    InvalidSortBy(String),
+    #[error("invalid slug: \"{0}\"")]
+    InvalidSlug(String),
+    #[error("invalid permalink: \"{0}\"")]
+    InvalidPermalink(String),
+    #[error("permalink pattern \"{0}\" uses the date but the page has no date keyword")]
+    PermalinkNeedsDate(String),
+    #[error("unknown permalink placeholder \"{0}\"")]
+    UnknownPermalinkPlaceholder(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_summary_at_more_marker() {
+        let source = "First paragraph.\n\n# more\n\nRest of the post.\n";
+
+        assert_eq!(split_summary(source, 100), ("First paragraph.\n\n", true));
+    }
+
+    #[test]
+    fn split_summary_ignores_marker_in_block() {
+        let source = "Intro.\n\n#+BEGIN_SRC org\n# more\n#+END_SRC\n\nMore text.\n";
+
+        assert_eq!(split_summary(source, 100), (source, false));
+    }
+
+    #[test]
+    fn split_summary_after_word_count() {
+        let source = "One two three.\n\nFour five six.\n\nSeven.\n";
+
+        assert_eq!(split_summary(source, 2), ("One two three.\n", true));
+        assert_eq!(
+            split_summary(source, 4),
+            ("One two three.\n\nFour five six.\n", true)
+        );
+        assert_eq!(split_summary(source, 100), (source, false));
+    }
+
+    #[test]
+    fn split_summary_does_not_count_blocks() {
+        let source = "One.\n#+BEGIN_SRC rust\nlet a = b + c;\n\n#+END_SRC\n\nTwo three.\n";
+
+        assert_eq!(split_summary(source, 2), (source, false));
+    }
+
+    fn write_files(root: &Path, files: &[&str]) {
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "#+TITLE: Page\n").unwrap();
+        }
+    }
+
+    fn config() -> SiteConfig {
+        SiteConfig::parse("[site]\nbase_url = \"https://example.com\"\n").unwrap()
+    }
+
+    #[test]
+    fn bundle_resources() {
+        let root = tempfile::tempdir().unwrap();
+        write_files(
+            root.path(),
+            &[
+                "content/posts/trip/index.org",
+                "content/posts/trip/map.png",
+                "content/posts/trip/.map.png~",
+                "content/posts/trip/photos/beach.jpg",
+                "content/posts/trip/day-one/index.org",
+                "content/posts/trip/day-one/boat.jpg",
+            ],
+        );
+
+        let path = root.path().join("content/posts/trip/index.org");
+        let page = Page::read_file(root.path(), path, &config(), None).unwrap();
+
+        assert_eq!(page.page_path(), Path::new("/posts/trip"));
+        assert_eq!(
+            page.resources()
+                .iter()
+                .map(|resource| (resource.name.as_str(), resource.path.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                ("map.png", "/posts/trip/map.png"),
+                ("photos/beach.jpg", "/posts/trip/photos/beach.jpg"),
+            ]
+        );
+    }
+
+    #[test]
+    fn content_index_is_not_a_bundle() {
+        let root = tempfile::tempdir().unwrap();
+        write_files(
+            root.path(),
+            &["content/index.org", "content/images/logo.png"],
+        );
+
+        let path = root.path().join("content/index.org");
+        let page = Page::read_file(root.path(), path, &config(), None).unwrap();
+
+        assert!(page.resources().is_empty());
+    }
+
+    #[test]
+    fn bundle_rejects_index_html() {
+        let root = tempfile::tempdir().unwrap();
+        write_files(
+            root.path(),
+            &["content/about/index.org", "content/about/index.html"],
+        );
+
+        let path = root.path().join("content/about/index.org");
+
+        assert!(Page::read_file(root.path(), path, &config(), None).is_err());
+    }
+
+    #[test]
+    fn slug_keyword() {
+        let parse = |slug: &str| {
+            let text = format!("#+TITLE: Post\n#+SLUG: {}\n", slug);
+            Page::parse("posts/a".into(), text, &config()).map(|page| page.page_path().to_owned())
+        };
+
+        assert_eq!(parse("first-post").unwrap(), Path::new("/posts/first-post"));
+        assert_eq!(parse("v1.2").unwrap(), Path::new("/posts/v1.2"));
+
+        for slug in &[".", "..", "a/b", "what?", "-"] {
+            assert!(parse(slug).is_err(), "{}", slug);
+        }
+    }
+
+    #[test]
+    fn description_falls_back_to_the_summary() {
+        let parse = |text: &str| Page::parse("/posts/a".into(), text.into(), &config()).unwrap();
+
+        let page = parse("#+TITLE: A\nThe *first* words.\n# more\nThe rest.\n");
+        assert_eq!(page.description(), Some("The first words."));
+        assert!(page.summary_truncated());
+
+        let page = parse("#+TITLE: A\n#+DESCRIPTION: Given\nThe first words.\n");
+        assert_eq!(page.description(), Some("Given"));
+        assert!(!page.summary_truncated());
+
+        assert_eq!(parse("#+TITLE: A\n").description(), None);
+        assert_eq!(cut_words("one two  three", 2), "one two…");
+    }
+
+    #[test]
+    fn source_block_offset_of_language() {
+        let text = "#+TITLE: A\n  #+begin_src nope :results output\nnope\n#+END_SRC\n";
+
+        assert_eq!(source_block_offset(text, "nope"), Some(25));
+        assert_eq!(&text[25..29], "nope");
+        assert_eq!(source_block_offset(text, "rust"), None);
+    }
+
+    #[test]
+    fn includes_are_relative_to_the_source() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("content/posts/a.org");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(
+            &path,
+            "#+TITLE: A\n#+INCLUDE: \"parts/intro.org\" :lines \"1-3\"\n\
+             #+include: code.rs src rust\n#+INCLUDE:\n",
+        )
+        .unwrap();
+
+        let page = Page::read_file(root.path(), path, &config(), None).unwrap();
+        let dir = root.path().join("content/posts");
+
+        assert_eq!(
+            page.includes(),
+            [dir.join("parts/intro.org"), dir.join("code.rs")]
+        );
+    }
+
+    #[test]
+    fn parse_series_with_part() {
+        assert_eq!(
+            parse_series(" Writing a parser #2 ").unwrap(),
+            (Some("Writing a parser".into()), Some(2))
+        );
+        assert_eq!(
+            parse_series("Writing a parser").unwrap(),
+            (Some("Writing a parser".into()), None)
+        );
+        assert_eq!(parse_series("").unwrap(), (None, None));
+    }
+
+    #[test]
+    fn parse_series_keeps_hash_in_name() {
+        assert_eq!(
+            parse_series("C# tips").unwrap(),
+            (Some("C# tips".into()), None)
+        );
+        assert_eq!(
+            parse_series("C# tips #3").unwrap(),
+            (Some("C# tips".into()), Some(3))
+        );
+    }
+
+    #[test]
+    fn parse_series_rejects_missing_name() {
+        assert!(parse_series("#2").is_err());
+    }
+
+    #[test]
+    fn fill_permalink_pattern_placeholders() {
+        let date = NaiveDate::from_ymd_opt(2021, 3, 7).unwrap();
+
+        assert_eq!(
+            fill_permalink_pattern(
+                ":section/:year/:month/:day/:slug",
+                "posts",
+                "hello",
+                Some(&date)
+            )
+            .unwrap(),
+            "posts/2021/03/07/hello"
+        );
+        assert_eq!(
+            fill_permalink_pattern("blog/:slug", "posts", "hello", None).unwrap(),
+            "blog/hello"
+        );
+    }
+
+    #[test]
+    fn fill_permalink_pattern_errors() {
+        assert!(matches!(
+            fill_permalink_pattern(":year/:slug", "posts", "hello", None),
+            Err(PageError::PermalinkNeedsDate(_))
+        ));
+        assert!(matches!(
+            fill_permalink_pattern(":author/:slug", "posts", "hello", None),
+            Err(PageError::UnknownPermalinkPlaceholder(_))
+        ));
+    }
 }
@@ -1,11 +1,22 @@
-use crate::page::Page;
+use crate::{
+    config::SiteRelatedConfig,
+    diagnostics::{Diagnostic, Diagnostics},
+    page::Page,
+    search::tokenize,
+    taxonomies::Taxonomies,
+};
 use anyhow::Result;
 use chrono::{Datelike, NaiveDate};
+use rust_stemmers::{Algorithm, Stemmer};
 use serde_derive::Serialize;
-use std::{collections::BTreeMap, path::PathBuf};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
 use thiserror::Error;
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct Post {
     title: String,
     description: String,
@@ -14,39 +25,169 @@ pub struct Post {
     date_updated: Option<NaiveDate>,
     year: i32,
     tags: Vec<String>,
+    series: Option<String>,
+    series_part: Option<u32>,
+    // Rendered summary, filled in once the documents are rendered
+    summary: Option<String>,
+    // Whether the post goes on after the summary, for a "read more" link
+    has_more: bool,
+    // Stemmed words of the title and text with their counts
+    #[serde(skip)]
+    terms: HashMap<String, usize>,
+}
+
+impl Post {
+    pub fn page_path(&self) -> &Path {
+        &self.page_path
+    }
+
+    fn link(&self) -> PostLink {
+        PostLink {
+            title: self.title.clone(),
+            page_path: self.page_path.clone(),
+            date: self.date,
+            series_part: self.series_part,
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct PostLink {
+    pub title: String,
+    pub page_path: PathBuf,
+    pub date: NaiveDate,
+    pub series_part: Option<u32>,
+}
+
+// The series of a post with its siblings in reading order
+#[derive(Serialize)]
+pub struct SeriesContext {
+    pub name: String,
+    pub part: Option<u32>,
+    pub posts: Vec<PostLink>,
+    pub prev: Option<PostLink>,
+    pub next: Option<PostLink>,
+}
+
+#[derive(Serialize)]
+pub struct ArchiveMonth {
+    pub month: u32,
+    pub count: usize,
+    pub path: String,
+    pub permalink: String,
+}
+
+// Overview of the posts of a year, newest first
+#[derive(Serialize)]
+pub struct ArchiveYear {
+    pub year: i32,
+    pub count: usize,
+    pub path: String,
+    pub permalink: String,
+    pub months: Vec<ArchiveMonth>,
 }
 
 #[derive(Default, Serialize)]
 pub struct Posts {
     posts: Vec<Post>,
     tags: BTreeMap<String, Vec<(String, PathBuf)>>,
+    // Series in reading order, by part number and then date
+    series: BTreeMap<String, Vec<PostLink>>,
+    // Most related posts first
+    related: BTreeMap<PathBuf, Vec<PostLink>>,
+}
+
+// Share of the tags of two posts they have in common
+fn tag_similarity(a: &Post, b: &Post) -> f64 {
+    if a.tags.is_empty() || b.tags.is_empty() {
+        return 0.0;
+    }
+
+    let shared = a.tags.iter().filter(|tag| b.tags.contains(tag)).count();
+    let total = a.tags.len() + b.tags.len() - shared;
+
+    shared as f64 / total as f64
+}
+
+// Cosine similarity of tf-idf weighted term counts
+fn content_similarity(a: &HashMap<String, f64>, b: &HashMap<String, f64>) -> f64 {
+    let dot: f64 = a
+        .iter()
+        .filter_map(|(term, weight)| b.get(term).map(|other| weight * other))
+        .sum();
+    let norm = |v: &HashMap<String, f64>| v.values().map(|w| w * w).sum::<f64>().sqrt();
+
+    let norms = norm(a) * norm(b);
+
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
 }
 
 impl Posts {
     pub fn add_page(&mut self, page: &Page) -> Result<()> {
         let page_path = page.page_path();
 
-        let date = page
-            .date()
-            .ok_or_else(|| PostsError::MissingDate(page_path.into()))?
-            .clone();
+        // Every missing field is reported at once
+        let mut diagnostics = Vec::new();
+        let mut missing = |error: PostsError, keyword: &str| {
+            diagnostics.push(
+                Diagnostic::error(error.to_string(), page.diagnostic_file())
+                    .with_help(format!("add a `#+{}:` keyword", keyword)),
+            );
+        };
+
+        if page.date().is_none() {
+            missing(PostsError::MissingDate(page_path.into()), "DATE");
+        }
+
+        if page.title().is_none() {
+            missing(PostsError::MissingTitle(page_path.into()), "TITLE");
+        }
+
+        // Pages fall back to their summary, so only posts without any text are missing one
+        let description = page.description().unwrap_or_default().to_string();
+
+        if description.is_empty() {
+            missing(
+                PostsError::MissingDescription(page_path.into()),
+                "DESCRIPTION",
+            );
+        }
+
+        if !diagnostics.is_empty() {
+            return Err(Diagnostics(diagnostics).into());
+        }
+
+        let date = *page.date().unwrap();
 
         let year = date.year();
 
+        let stemmer = Stemmer::create(Algorithm::English);
+        let mut terms = HashMap::new();
+
+        for term in tokenize(
+            &format!("{} {}", page.title().unwrap(), page.body_text()),
+            Some(&stemmer),
+        ) {
+            *terms.entry(term).or_default() += 1;
+        }
+
         let post = Post {
-            title: page
-                .title()
-                .ok_or_else(|| PostsError::MissingTitle(page_path.into()))?
-                .into(),
-            description: page
-                .description()
-                .ok_or_else(|| PostsError::MissingDescription(page_path.into()))?
-                .into(),
+            title: page.title().unwrap().into(),
+            description,
             page_path: page_path.into(),
             date,
-            date_updated: page.date_updated().map(|d| d.clone()),
+            date_updated: page.date_updated().copied(),
             year,
             tags: page.tags().to_vec(),
+            series: page.series().map(|s| s.into()),
+            series_part: page.series_part(),
+            summary: None,
+            has_more: page.summary_truncated(),
+            terms,
         };
 
         self.posts.push(post);
@@ -55,17 +196,246 @@ impl Posts {
     }
 
     pub fn sort(&mut self) {
-        self.posts.sort_by(|a, b| b.date.cmp(&a.date))
+        self.posts.sort_by_key(|post| Reverse(post.date))
     }
 
-    pub fn generate_tag_index(&mut self) {
-        for post in &self.posts {
+    // Tags are the terms of the tags taxonomy, so tags with the same slug are merged
+    pub fn generate_tag_index(&mut self, taxonomies: &Taxonomies) {
+        let taxonomy = match taxonomies.get("tags") {
+            Some(taxonomy) => taxonomy,
+            None => return,
+        };
+
+        for post in &mut self.posts {
+            let mut tags: Vec<String> = Vec::new();
+
             for tag in &post.tags {
-                let tag_entry = self.tags.entry(tag.clone()).or_insert_with(|| Vec::new());
+                if let Some(term) = taxonomy.term(tag) {
+                    if !tags.contains(&term.name) {
+                        tags.push(term.name.clone());
+                    }
+                }
+            }
+
+            post.tags = tags;
+
+            for tag in &post.tags {
+                self.tags
+                    .entry(tag.clone())
+                    .or_default()
+                    .push((post.title.clone(), post.page_path.clone()));
+            }
+        }
+    }
+
+    pub fn generate_series_index(&mut self) {
+        for post in &self.posts {
+            if let Some(series) = &post.series {
+                self.series
+                    .entry(series.clone())
+                    .or_default()
+                    .push(post.link());
+            }
+        }
+
+        for posts in self.series.values_mut() {
+            posts.sort_by_key(|post| (post.series_part.unwrap_or(u32::MAX), post.date));
+        }
+    }
+
+    pub fn generate_related(&mut self, config: &SiteRelatedConfig) {
+        if config.count == 0 {
+            return;
+        }
+
+        // Words in fewer posts say more about what a post is about
+        let mut document_counts: HashMap<&str, usize> = HashMap::new();
+
+        for post in &self.posts {
+            for term in post.terms.keys() {
+                *document_counts.entry(term).or_default() += 1;
+            }
+        }
+
+        let post_count = self.posts.len() as f64;
+
+        let weights: Vec<HashMap<String, f64>> = self
+            .posts
+            .iter()
+            .map(|post| {
+                post.terms
+                    .iter()
+                    .map(|(term, count)| {
+                        let idf = (post_count / document_counts[term.as_str()] as f64).ln();
+
+                        (term.clone(), *count as f64 * idf)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        for (i, post) in self.posts.iter().enumerate() {
+            let mut scored: Vec<(f64, &Post)> = self
+                .posts
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(j, other)| {
+                    let score = config.tags_weight * tag_similarity(post, other)
+                        + config.content_weight * content_similarity(&weights[i], &weights[j]);
+
+                    (score, other)
+                })
+                .filter(|(score, _)| *score > 0.0)
+                .collect();
+
+            // Posts are sorted newest first, which the stable sort keeps for equal scores
+            scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+            self.related.insert(
+                post.page_path.clone(),
+                scored
+                    .into_iter()
+                    .take(config.count)
+                    .map(|(_, other)| other.link())
+                    .collect(),
+            );
+        }
+    }
+
+    pub fn related(&self, page_path: &Path) -> &[PostLink] {
+        self.related
+            .get(page_path)
+            .map(|related| related.as_slice())
+            .unwrap_or_default()
+    }
+
+    // The older and newer post of a post in the chronological order
+    pub fn navigation(&self, page_path: &Path) -> (Option<PostLink>, Option<PostLink>) {
+        // Posts are sorted newest first
+        match self
+            .posts
+            .iter()
+            .position(|post| post.page_path == page_path)
+        {
+            Some(i) => (
+                self.posts.get(i + 1).map(|post| post.link()),
+                i.checked_sub(1)
+                    .and_then(|i| self.posts.get(i))
+                    .map(|post| post.link()),
+            ),
+            None => (None, None),
+        }
+    }
+
+    pub fn series_context(&self, page_path: &Path) -> Option<SeriesContext> {
+        let post = self.posts.iter().find(|post| post.page_path == page_path)?;
+        let name = post.series.as_ref()?;
+        let posts = self.series.get(name)?;
+
+        let i = posts.iter().position(|p| p.page_path == page_path)?;
+
+        Some(SeriesContext {
+            name: name.clone(),
+            part: post.series_part,
+            posts: posts.clone(),
+            prev: i.checked_sub(1).and_then(|i| posts.get(i)).cloned(),
+            next: posts.get(i + 1).cloned(),
+        })
+    }
+
+    // The posts at the given page paths, such as the posts of a taxonomy term
+    pub fn subset(&self, page_paths: &[PathBuf]) -> Posts {
+        let mut posts = Posts {
+            posts: self
+                .posts
+                .iter()
+                .filter(|post| page_paths.contains(&post.page_path))
+                .cloned()
+                .collect(),
+            ..Posts::default()
+        };
+
+        posts.tags = self
+            .tags
+            .iter()
+            .filter_map(|(tag, tag_posts)| {
+                let tag_posts: Vec<(String, PathBuf)> = tag_posts
+                    .iter()
+                    .filter(|(_, page_path)| page_paths.contains(page_path))
+                    .cloned()
+                    .collect();
+
+                Some((tag.clone(), tag_posts)).filter(|(_, tag_posts)| !tag_posts.is_empty())
+            })
+            .collect();
+        posts.generate_series_index();
+
+        posts
+    }
 
-                tag_entry.push((post.title.clone(), post.page_path.clone()));
+    // Rendered summaries keyed by page path, posts without one keep none
+    pub fn set_summaries(&mut self, mut summaries: HashMap<PathBuf, String>) {
+        for post in &mut self.posts {
+            post.summary = summaries.remove(&post.page_path);
+        }
+    }
+
+    pub fn posts(&self) -> &[Post] {
+        &self.posts
+    }
+
+    pub fn tags(&self) -> &BTreeMap<String, Vec<(String, PathBuf)>> {
+        &self.tags
+    }
+
+    // Years and months with posts, the paths are below the archive path such as "/posts"
+    pub fn archives(&self, base_url: &str, archive_path: &str) -> Vec<ArchiveYear> {
+        let archive_path = archive_path.trim_end_matches('/');
+        let mut archives: Vec<ArchiveYear> = Vec::new();
+
+        // Posts are sorted newest first
+        for post in &self.posts {
+            let month = post.date.month();
+
+            if archives.last().map(|a| a.year) != Some(post.year) {
+                let path = format!("{}/{}/", archive_path, post.year);
+
+                archives.push(ArchiveYear {
+                    year: post.year,
+                    count: 0,
+                    permalink: format!("{}{}", base_url, path),
+                    path,
+                    months: Vec::new(),
+                });
+            }
+
+            let archive_year = archives.last_mut().unwrap();
+            archive_year.count += 1;
+
+            if archive_year.months.last().map(|m| m.month) != Some(month) {
+                let path = format!("{}/{}/{:02}/", archive_path, post.year, month);
+
+                archive_year.months.push(ArchiveMonth {
+                    month,
+                    count: 0,
+                    permalink: format!("{}{}", base_url, path),
+                    path,
+                });
             }
+
+            archive_year.months.last_mut().unwrap().count += 1;
         }
+
+        archives
+    }
+
+    // Posts of a year, or a month of it
+    pub fn in_archive(&self, year: i32, month: Option<u32>) -> Vec<&Post> {
+        self.posts
+            .iter()
+            .filter(|post| post.year == year && month.is_none_or(|m| post.date.month() == m))
+            .collect()
     }
 
     pub fn last_update(&self) -> Option<NaiveDate> {
@@ -80,8 +450,160 @@ impl Posts {
 pub enum PostsError {
     #[error("post at \"{0:?}\" is missing the title keyword")]
     MissingTitle(PathBuf),
-    #[error("post at \"{0:?}\" is missing the description keyword")]
+    #[error("post at \"{0:?}\" has no description keyword and no text to summarize")]
     MissingDescription(PathBuf),
     #[error("post at \"{0:?}\" is missing the date keyword")]
     MissingDate(PathBuf),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SiteConfig;
+
+    fn posts(pages: &[(&str, &str, &str)]) -> Posts {
+        let config = SiteConfig::parse("[site]\nbase_url = \"https://example.com\"\n").unwrap();
+        let mut posts = Posts::default();
+
+        for (path, date, series) in pages {
+            let text = format!(
+                "#+TITLE: {}\n#+DATE: <{}>\n#+SERIES: {}\n\nText.\n",
+                path, date, series
+            );
+            let page = Page::parse(path.into(), text, &config).unwrap();
+            posts.add_page(&page).unwrap();
+        }
+
+        posts.sort();
+        posts.generate_series_index();
+
+        posts
+    }
+
+    fn paths(links: &[PostLink]) -> Vec<&Path> {
+        links.iter().map(|link| link.page_path.as_path()).collect()
+    }
+
+    #[test]
+    fn series_in_part_order() {
+        let posts = posts(&[
+            ("/posts/extra", "2021-01-01 Fri", "Parser"),
+            ("/posts/two", "2021-02-01 Mon", "Parser #2"),
+            ("/posts/one", "2021-03-01 Mon", "Parser #1"),
+            ("/posts/other", "2021-04-01 Thu", ""),
+        ]);
+
+        let series = posts.series_context(Path::new("/posts/two")).unwrap();
+
+        assert_eq!(series.name, "Parser");
+        assert_eq!(series.part, Some(2));
+        assert_eq!(
+            paths(&series.posts),
+            ["/posts/one", "/posts/two", "/posts/extra"]
+        );
+        assert_eq!(series.prev.unwrap().page_path, Path::new("/posts/one"));
+        assert_eq!(series.next.unwrap().page_path, Path::new("/posts/extra"));
+        assert!(posts.series_context(Path::new("/posts/other")).is_none());
+    }
+
+    #[test]
+    fn navigation_by_date() {
+        let posts = posts(&[
+            ("/posts/b", "2021-02-01 Mon", ""),
+            ("/posts/a", "2021-01-01 Fri", ""),
+            ("/posts/c", "2021-03-01 Mon", ""),
+        ]);
+
+        let (older, newer) = posts.navigation(Path::new("/posts/b"));
+        assert_eq!(older.unwrap().page_path, Path::new("/posts/a"));
+        assert_eq!(newer.unwrap().page_path, Path::new("/posts/c"));
+
+        let (older, newer) = posts.navigation(Path::new("/posts/c"));
+        assert_eq!(older.unwrap().page_path, Path::new("/posts/b"));
+        assert!(newer.is_none());
+
+        assert!(posts.navigation(Path::new("/missing")).0.is_none());
+    }
+
+    #[test]
+    fn archives_count_years_and_months() {
+        let posts = posts(&[
+            ("/posts/a", "2020-12-01 Tue", ""),
+            ("/posts/b", "2021-01-01 Fri", ""),
+            ("/posts/c", "2021-01-15 Fri", ""),
+            ("/posts/d", "2021-03-01 Mon", ""),
+        ]);
+
+        let archives = posts.archives("https://example.com", "/posts/");
+
+        assert_eq!(archives.len(), 2);
+        assert_eq!((archives[0].year, archives[0].count), (2021, 3));
+        assert_eq!(archives[0].path, "/posts/2021/");
+        assert_eq!(archives[0].permalink, "https://example.com/posts/2021/");
+        assert_eq!(
+            archives[0]
+                .months
+                .iter()
+                .map(|month| (month.month, month.count, month.path.as_str()))
+                .collect::<Vec<_>>(),
+            [(3, 1, "/posts/2021/03/"), (1, 2, "/posts/2021/01/")]
+        );
+        assert_eq!((archives[1].year, archives[1].count), (2020, 1));
+
+        assert_eq!(posts.in_archive(2021, None).len(), 3);
+        assert_eq!(posts.in_archive(2021, Some(1)).len(), 2);
+        assert!(posts.in_archive(2019, None).is_empty());
+    }
+
+    fn related_posts(tags_weight: f64, content_weight: f64) -> Posts {
+        let config = SiteConfig::parse("[site]\nbase_url = \"https://example.com\"\n").unwrap();
+        let mut posts = Posts::default();
+
+        for (path, tags, text) in &[
+            ("/posts/a", "rust parser", "Lexer tokens grammar."),
+            ("/posts/b", "rust parser", "Cooking recipes."),
+            ("/posts/c", "travel", "Lexer tokens grammar."),
+            ("/posts/d", "travel", "Beach sun."),
+        ] {
+            let text = format!(
+                "#+TITLE: Post\n#+DATE: <2021-01-01 Fri>\n#+TAGS: {}\n\n{}\n",
+                tags, text
+            );
+            let page = Page::parse(path.into(), text, &config).unwrap();
+            posts.add_page(&page).unwrap();
+        }
+
+        posts.sort();
+        posts.generate_related(&SiteRelatedConfig {
+            count: 2,
+            tags_weight,
+            content_weight,
+        });
+
+        posts
+    }
+
+    #[test]
+    fn related_by_tags_and_content() {
+        let by_tags = related_posts(1.0, 0.0);
+        assert_eq!(paths(by_tags.related(Path::new("/posts/a"))), ["/posts/b"]);
+
+        let by_content = related_posts(0.0, 1.0);
+        assert_eq!(
+            paths(by_content.related(Path::new("/posts/a"))),
+            ["/posts/c"]
+        );
+        assert!(by_content.related(Path::new("/posts/d")).is_empty());
+
+        // Both weights add up, the tags count more here
+        let both = related_posts(2.0, 1.0);
+        assert_eq!(
+            paths(both.related(Path::new("/posts/a"))),
+            ["/posts/b", "/posts/c"]
+        );
+
+        let none = related_posts(0.0, 0.0);
+        assert!(none.related(Path::new("/posts/a")).is_empty());
+        assert!(none.related(Path::new("/missing")).is_empty());
+    }
+}
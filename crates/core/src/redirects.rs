@@ -0,0 +1,209 @@
+use crate::{diagnostics::Diagnostics, page::Page};
+use anyhow::Result;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+// Old paths of pages from their #+ALIASES keyword, mapped to the page paths
+#[derive(Clone, Debug, Default)]
+pub struct Redirects {
+    redirects: BTreeMap<String, String>,
+}
+
+// Page paths as linked to, with a trailing slash
+fn target_path(page_path: &Path) -> String {
+    let path: String = page_path.to_string_lossy().into();
+
+    if path.ends_with('/') {
+        path
+    } else {
+        format!("{}/", path)
+    }
+}
+
+impl Redirects {
+    // Generated paths are the listings and other pages besides the content pages
+    pub fn collect(
+        pages: &HashMap<PathBuf, Page>,
+        generated_paths: &BTreeSet<String>,
+    ) -> Result<Redirects> {
+        let mut page_paths: Vec<&PathBuf> = pages.keys().collect();
+        page_paths.sort();
+
+        let mut redirects: BTreeMap<String, String> = BTreeMap::new();
+        let mut diagnostics = Vec::new();
+
+        for page_path in page_paths {
+            let page = &pages[page_path];
+
+            for alias in page.aliases() {
+                let error = if pages.contains_key(Path::new(alias)) {
+                    RedirectsError::AliasIsPage(alias.clone())
+                } else if generated_paths.contains(alias) {
+                    RedirectsError::AliasIsGenerated(alias.clone())
+                } else if let Some(target) = redirects.get(alias) {
+                    RedirectsError::DuplicateAlias(alias.clone(), target.clone())
+                } else {
+                    redirects.insert(alias.clone(), target_path(page_path));
+
+                    continue;
+                };
+
+                diagnostics.push(page.keyword_diagnostic(error.to_string(), "ALIASES", alias));
+            }
+        }
+
+        if !diagnostics.is_empty() {
+            return Err(Diagnostics(diagnostics).into());
+        }
+
+        Ok(Redirects { redirects })
+    }
+
+    // Looks up a request path with or without the trailing slash
+    pub fn get(&self, path: &str) -> Option<&str> {
+        self.redirects
+            .get(path.trim_end_matches('/'))
+            .map(|target| target.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.redirects.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.redirects.iter()
+    }
+
+    // Pages for static hosts without redirect support
+    pub fn redirect_page(base_url: &str, target: &str) -> String {
+        let url = tera::escape_html(&format!("{}{}", base_url, target));
+
+        format!(
+            "<!DOCTYPE html><html><head><meta charset=utf-8><title>Redirect</title>\
+             <link rel=canonical href=\"{0}\"><meta http-equiv=refresh content=\"0; url={0}\">\
+             </head><body><a href=\"{0}\">{0}</a></body></html>",
+            url
+        )
+    }
+
+    // A _redirects file as read by Netlify and Cloudflare Pages
+    pub fn redirects_file(&self) -> String {
+        self.redirects
+            .iter()
+            .map(|(alias, target)| format!("{} {} 301\n", alias, target))
+            .collect()
+    }
+
+    // Entries for an nginx map block, such as `map $uri $redirect { include redirects.map; }`
+    pub fn nginx_map(&self) -> String {
+        self.redirects
+            .iter()
+            .map(|(alias, target)| format!("{0} {1};\n{0}/ {1};\n", alias, target))
+            .collect()
+    }
+
+    pub fn write(&self, out_path: &Path, base_url: &str) -> Result<()> {
+        for (alias, target) in &self.redirects {
+            let alias_path = out_path.join(alias.trim_start_matches('/'));
+
+            fs::create_dir_all(&alias_path)?;
+            fs::write(
+                alias_path.join("index.html"),
+                Redirects::redirect_page(base_url, target),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum RedirectsError {
+    #[error("alias \"{0}\" is the path of a page")]
+    AliasIsPage(String),
+    #[error("alias \"{0}\" is the path of a generated page")]
+    AliasIsGenerated(String),
+    #[error("alias \"{0}\" already redirects to \"{1}\"")]
+    DuplicateAlias(String, String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SiteConfig;
+
+    fn pages(files: &[(&str, &str)]) -> HashMap<PathBuf, Page> {
+        let config = SiteConfig::parse("[site]\nbase_url = \"https://example.com\"\n").unwrap();
+
+        files
+            .iter()
+            .map(|(path, text)| {
+                let page = Page::parse(path.into(), text.to_string(), &config).unwrap();
+
+                (page.page_path().to_path_buf(), page)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn aliases_redirect_to_their_page() {
+        let pages = pages(&[(
+            "/posts/new",
+            "#+TITLE: New\n#+ALIASES: /old-post /blog/old/\n",
+        )]);
+
+        let redirects = Redirects::collect(&pages, &BTreeSet::new()).unwrap();
+
+        assert_eq!(redirects.get("/old-post/"), Some("/posts/new/"));
+        assert_eq!(redirects.get("/blog/old"), Some("/posts/new/"));
+        assert_eq!(redirects.get("/posts/new"), None);
+        assert_eq!(
+            redirects.redirects_file(),
+            "/blog/old /posts/new/ 301\n/old-post /posts/new/ 301\n"
+        );
+        assert!(redirects
+            .nginx_map()
+            .contains("/old-post /posts/new/;\n/old-post/ /posts/new/;\n"));
+
+        let out = tempfile::tempdir().unwrap();
+        redirects.write(out.path(), "https://example.com").unwrap();
+
+        let page = fs::read_to_string(out.path().join("blog/old/index.html")).unwrap();
+        assert!(page.contains(
+            "content=\"0; url=https:&#x2F;&#x2F;example.com&#x2F;posts&#x2F;new&#x2F;\""
+        ));
+    }
+
+    #[test]
+    fn aliases_may_not_shadow_other_paths() {
+        let pages = pages(&[
+            ("/a", "#+TITLE: A\n#+ALIASES: /b /tags /old\n"),
+            ("/b", "#+TITLE: B\n#+ALIASES: /old\n"),
+        ]);
+        let generated = BTreeSet::from(["/tags".to_string()]);
+
+        let err = Redirects::collect(&pages, &generated)
+            .unwrap_err()
+            .to_string();
+
+        assert!(
+            err.contains("alias \"/b\" is the path of a page"),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains("alias \"/tags\" is the path of a generated page"),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains("alias \"/old\" already redirects to \"/a/\""),
+            "{}",
+            err
+        );
+    }
+}
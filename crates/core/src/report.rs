@@ -0,0 +1,189 @@
+use crate::diagnostics::Diagnostic;
+use anyhow::Result;
+use glob::glob;
+use serde_derive::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+    time::Duration,
+};
+
+#[derive(Debug, Serialize)]
+pub struct PhaseReport {
+    pub name: String,
+    pub duration_ms: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OutputReport {
+    // Path relative to the out dir
+    pub path: String,
+    pub source: Option<String>,
+    pub template: Option<String>,
+    pub bytes: u64,
+    // Bytes of the .gz and .br variants written next to the output
+    pub compressed_bytes: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BuildReport {
+    pub pages: usize,
+    // Pages copied from the previous build by the build cache
+    pub cached_pages: usize,
+    pub posts: usize,
+    pub tags: usize,
+    // Files which are not rendered from a template, such as static files and images
+    pub assets: usize,
+    pub bytes_written: u64,
+    pub duration_ms: f64,
+    pub phases: Vec<PhaseReport>,
+    pub outputs: Vec<OutputReport>,
+    // Problems which did not stop the build, printed by the caller
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl BuildReport {
+    pub fn add_phase(&mut self, name: &str, duration: Duration) {
+        self.phases.push(PhaseReport {
+            name: name.into(),
+            duration_ms: duration.as_secs_f64() * 1000.0,
+        });
+    }
+
+    pub fn add_output(&mut self, path: &str, source: Option<String>, template: Option<&str>) {
+        self.outputs.push(OutputReport {
+            path: path.trim_start_matches('/').into(),
+            source,
+            template: template.map(|t| t.into()),
+            bytes: 0,
+            compressed_bytes: 0,
+        });
+    }
+
+    // Fills in the sizes and adds every other file in the out dir as an asset,
+    // compressed variants count towards the file they were made from
+    pub fn add_files(&mut self, out_path: &Path) -> Result<()> {
+        let mut sizes = BTreeMap::new();
+
+        for file in glob(&format!("{}/**/*", out_path.to_string_lossy()))?
+            .filter_map(|p| p.ok())
+            .filter(|p| p.is_file())
+        {
+            let path: String = file.strip_prefix(out_path)?.to_string_lossy().into();
+            sizes.insert(path, fs::metadata(&file)?.len());
+        }
+
+        let mut indices: HashMap<String, usize> = self
+            .outputs
+            .iter()
+            .enumerate()
+            .map(|(index, output)| (output.path.clone(), index))
+            .collect();
+
+        let variant_of = |path: &str| {
+            [".gz", ".br"]
+                .iter()
+                .filter_map(|extension| path.strip_suffix(extension))
+                .find(|source| sizes.contains_key(*source))
+                .map(String::from)
+        };
+
+        let (variants, files): (Vec<_>, Vec<_>) = sizes
+            .iter()
+            .partition(|(path, _)| variant_of(path).is_some());
+
+        for (path, bytes) in files {
+            self.bytes_written += bytes;
+
+            match indices.get(path) {
+                Some(&index) => self.outputs[index].bytes = *bytes,
+                None => {
+                    self.assets += 1;
+                    indices.insert(path.clone(), self.outputs.len());
+                    self.outputs.push(OutputReport {
+                        path: path.clone(),
+                        source: None,
+                        template: None,
+                        bytes: *bytes,
+                        compressed_bytes: 0,
+                    });
+                }
+            }
+        }
+
+        for (path, bytes) in variants {
+            self.bytes_written += bytes;
+
+            if let Some(&index) = variant_of(path).and_then(|source| indices.get(&source)) {
+                self.outputs[index].compressed_bytes += bytes;
+            }
+        }
+
+        self.outputs.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_files_sizes_outputs_and_assets() {
+        let out = tempfile::tempdir().unwrap();
+        fs::create_dir_all(out.path().join("posts/a")).unwrap();
+        fs::write(out.path().join("posts/a/index.html"), "<p>post</p>").unwrap();
+        fs::write(out.path().join("posts/a/index.html.gz"), "gz").unwrap();
+        fs::write(out.path().join("style.css"), "body{}").unwrap();
+
+        let mut report = BuildReport::default();
+        report.add_output(
+            "/posts/a/index.html",
+            Some("content/posts/a.org".into()),
+            Some("page.html"),
+        );
+        report.add_files(out.path()).unwrap();
+
+        assert_eq!(report.assets, 1);
+        assert_eq!(report.bytes_written, 11 + 2 + 6);
+        assert_eq!(report.outputs.len(), 2);
+        assert_eq!(report.outputs[0].path, "posts/a/index.html");
+        assert_eq!(report.outputs[0].bytes, 11);
+        assert_eq!(report.outputs[0].compressed_bytes, 2);
+        assert_eq!(report.outputs[1].path, "style.css");
+        assert_eq!(report.outputs[1].source, None);
+    }
+
+    #[test]
+    fn json_report_lists_outputs() {
+        let mut report = BuildReport {
+            pages: 1,
+            ..BuildReport::default()
+        };
+        report.add_phase("pages", Duration::from_millis(2));
+        report.add_output(
+            "/index.html",
+            Some("content/_index.org".into()),
+            Some("index.html"),
+        );
+
+        let json = serde_json::to_value(&report).unwrap();
+
+        assert_eq!(json["pages"], 1);
+        assert_eq!(json["phases"][0]["name"], "pages");
+        assert_eq!(json["phases"][0]["duration_ms"], 2.0);
+        assert_eq!(
+            json["outputs"][0],
+            serde_json::json!({
+                "path": "index.html",
+                "source": "content/_index.org",
+                "template": "index.html",
+                "bytes": 0,
+                "compressed_bytes": 0,
+            })
+        );
+        assert_eq!(json["warnings"], serde_json::json!([]));
+    }
+}
@@ -0,0 +1,323 @@
+use crate::{
+    config::SiteSearchConfig,
+    page::{normalize_page_path, Page},
+    sitemap::Sitemap,
+};
+use anyhow::Result;
+use orgize::{elements::Element, Event, Org};
+use rust_stemmers::{Algorithm, Stemmer};
+use serde_derive::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+pub const FIELDS: [&str; 5] = ["title", "description", "tags", "headings", "body"];
+
+#[derive(Debug, Default, Serialize)]
+pub struct SearchDocument {
+    pub permalink: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub headings: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+impl SearchDocument {
+    fn field_text(&self, field: &str) -> String {
+        match field {
+            "title" => self.title.clone().unwrap_or_default(),
+            "description" => self.description.clone().unwrap_or_default(),
+            "tags" => self.tags.clone().unwrap_or_default().join(" "),
+            "headings" => self.headings.clone().unwrap_or_default().join(" "),
+            "body" => self.body.clone().unwrap_or_default(),
+            _ => String::new(),
+        }
+    }
+}
+
+// Terms map to [document, field, count] entries, the indices point into documents and fields
+#[derive(Debug, Default, Serialize)]
+pub struct InvertedIndex {
+    pub fields: Vec<String>,
+    pub stemming: bool,
+    pub terms: BTreeMap<String, Vec<[usize; 3]>>,
+}
+
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    pub documents: Vec<SearchDocument>,
+    pub inverted: InvertedIndex,
+}
+
+// Headings and the text outside of them, leaving out markup and source blocks
+pub(crate) fn extract_document_text(document: &Org) -> (Vec<String>, String) {
+    let mut headings = Vec::new();
+    let mut body = String::new();
+    let mut in_title = false;
+
+    for event in document.iter() {
+        match event {
+            Event::Start(Element::Title(title)) => {
+                headings.push(title.raw.trim().to_string());
+                in_title = true;
+            }
+            Event::End(Element::Title(_)) => in_title = false,
+            Event::Start(Element::Text { value })
+            | Event::Start(Element::Code { value })
+            | Event::Start(Element::Verbatim { value })
+                if !in_title =>
+            {
+                body.push_str(value);
+                body.push(' ');
+            }
+            Event::End(Element::Paragraph { .. }) => body.push(' '),
+            _ => {}
+        }
+    }
+
+    let body = body.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+    (headings, body)
+}
+
+// Lowercase words, stemmed when there is a stemmer
+pub fn tokenize(text: &str, stemmer: Option<&Stemmer>) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() > 1)
+        .map(|word| {
+            let word = word.to_lowercase();
+
+            match stemmer {
+                Some(stemmer) => stemmer.stem(&word).into_owned(),
+                None => word,
+            }
+        })
+        .collect()
+}
+
+impl SearchIndex {
+    pub fn build(
+        pages: &HashMap<PathBuf, Page>,
+        sitemap: &Sitemap,
+        config: &SiteSearchConfig,
+    ) -> Result<SearchIndex> {
+        for field in &config.fields {
+            if !FIELDS.contains(&field.as_str()) {
+                return Err(SearchError::UnknownField(field.clone()).into());
+            }
+        }
+
+        let has_field = |field: &str| config.fields.iter().any(|f| f == field);
+
+        // Excluded paths may be written with or without the slashes around them
+        let excludes: Vec<PathBuf> = config
+            .exclude
+            .iter()
+            .map(|exclude| normalize_page_path(exclude))
+            .collect();
+
+        // Sorted so the index does not change between builds of the same content
+        let mut page_paths: Vec<&PathBuf> = pages
+            .keys()
+            .filter(|page_path| {
+                let page_path = normalize_page_path(&page_path.to_string_lossy());
+
+                !excludes
+                    .iter()
+                    .any(|exclude| page_path.starts_with(exclude))
+            })
+            .collect();
+        page_paths.sort();
+
+        let mut documents = Vec::new();
+
+        for page_path in page_paths {
+            let page = &pages[page_path];
+            let (headings, body) = (page.headings().to_vec(), page.body_text().to_string());
+
+            let permalink = sitemap
+                .get(&page_path.to_string_lossy())
+                .map(|entry| entry.permalink.clone())
+                .unwrap_or_default();
+
+            documents.push(SearchDocument {
+                permalink,
+                title: page
+                    .title()
+                    .filter(|_| has_field("title"))
+                    .map(|t| t.into()),
+                description: page
+                    .description()
+                    .filter(|_| has_field("description"))
+                    .map(|d| d.into()),
+                tags: Some(page.tags().to_vec()).filter(|_| has_field("tags")),
+                headings: Some(headings).filter(|_| has_field("headings")),
+                body: Some(body).filter(|_| has_field("body")),
+            });
+        }
+
+        let stemmer = if config.stemming {
+            Some(Stemmer::create(Algorithm::English))
+        } else {
+            None
+        };
+
+        let mut terms: BTreeMap<String, Vec<[usize; 3]>> = BTreeMap::new();
+
+        for (document_index, document) in documents.iter().enumerate() {
+            for (field_index, field) in config.fields.iter().enumerate() {
+                let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+
+                for token in tokenize(&document.field_text(field), stemmer.as_ref()) {
+                    *counts.entry(token).or_default() += 1;
+                }
+
+                for (term, count) in counts {
+                    terms
+                        .entry(term)
+                        .or_default()
+                        .push([document_index, field_index, count]);
+                }
+            }
+        }
+
+        Ok(SearchIndex {
+            documents,
+            inverted: InvertedIndex {
+                fields: config.fields.clone(),
+                stemming: config.stemming,
+                terms,
+            },
+        })
+    }
+
+    pub fn write(&self, out_path: &Path) -> Result<()> {
+        fs::write(
+            out_path.join("search-index.json"),
+            serde_json::to_string(&self.documents)?,
+        )?;
+        fs::write(
+            out_path.join("search-terms.json"),
+            serde_json::to_string(&self.inverted)?,
+        )?;
+
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum SearchError {
+    #[error(
+        "unknown search field \"{0}\", the fields are title, description, tags, headings and body"
+    )]
+    UnknownField(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SiteConfig;
+
+    fn pages(files: &[(&str, &str)]) -> HashMap<PathBuf, Page> {
+        let config = SiteConfig::parse("[site]\nbase_url = \"https://example.com\"\n").unwrap();
+
+        files
+            .iter()
+            .map(|(path, text)| {
+                let page = Page::parse(path.into(), text.to_string(), &config).unwrap();
+
+                (page.page_path().to_path_buf(), page)
+            })
+            .collect()
+    }
+
+    fn search_config(fields: &[&str], exclude: &[&str], stemming: bool) -> SiteSearchConfig {
+        SiteSearchConfig {
+            enabled: true,
+            fields: fields.iter().map(|field| field.to_string()).collect(),
+            exclude: exclude.iter().map(|path| path.to_string()).collect(),
+            stemming,
+        }
+    }
+
+    #[test]
+    fn tokenize_words() {
+        let stemmer = Stemmer::create(Algorithm::English);
+
+        assert_eq!(tokenize("Parsing a C-parser!", None), ["parsing", "parser"]);
+        assert_eq!(
+            tokenize("Parsing a C-parser!", Some(&stemmer)),
+            ["pars", "parser"]
+        );
+    }
+
+    #[test]
+    fn document_text_without_markup() {
+        let org = Org::parse(
+            "Intro *bold* =code=.\n* First heading\nBody\n\
+             #+BEGIN_SRC rust\nfn main() {}\n#+END_SRC\n",
+        );
+
+        let (headings, body) = extract_document_text(&org);
+
+        assert_eq!(headings, ["First heading"]);
+        assert_eq!(body, "Intro bold code . Body");
+    }
+
+    #[test]
+    fn index_fields_and_terms() {
+        let pages = pages(&[
+            (
+                "/posts/b",
+                "#+TITLE: Parsers\n#+TAGS: rust\nWriting parsers.\n",
+            ),
+            ("/posts/a", "#+TITLE: Trips\n#+DESCRIPTION: Parsing maps\n"),
+            ("/drafts/c", "#+TITLE: Parsers draft\n"),
+        ]);
+        let config = search_config(&["title", "tags"], &["drafts/"], false);
+
+        let index = SearchIndex::build(&pages, &Sitemap::default(), &config).unwrap();
+
+        let titles: Vec<_> = index.documents.iter().map(|d| d.title.as_deref()).collect();
+        assert_eq!(titles, [Some("Trips"), Some("Parsers")]);
+        assert!(index.documents[0].description.is_none());
+        assert_eq!(
+            index.documents[1].tags.as_deref(),
+            Some(&["rust".to_string()][..])
+        );
+        assert!(index.documents[1].body.is_none());
+
+        assert_eq!(index.inverted.terms["parsers"], [[1, 0, 1]]);
+        assert_eq!(index.inverted.terms["rust"], [[1, 1, 1]]);
+        assert!(!index.inverted.terms.contains_key("writing"));
+
+        let out = tempfile::tempdir().unwrap();
+        index.write(out.path()).unwrap();
+        let terms: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(out.path().join("search-terms.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(terms["fields"], serde_json::json!(["title", "tags"]));
+        assert!(out.path().join("search-index.json").is_file());
+    }
+
+    #[test]
+    fn index_rejects_unknown_fields() {
+        let config = search_config(&["title", "author"], &[], true);
+
+        let err = SearchIndex::build(&HashMap::new(), &Sitemap::default(), &config).unwrap_err();
+
+        assert!(err
+            .to_string()
+            .starts_with("unknown search field \"author\""));
+    }
+}
@@ -0,0 +1,245 @@
+use crate::{page::Page, sitemap::Sitemap};
+use chrono::NaiveDate;
+use serde_derive::Serialize;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SectionPage {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub page_path: PathBuf,
+    pub permalink: String,
+    pub date: Option<NaiveDate>,
+    pub weight: Option<i64>,
+}
+
+impl SectionPage {
+    fn new(page: &Page, sitemap: &Sitemap) -> SectionPage {
+        SectionPage {
+            title: page.title().map(|t| t.into()),
+            description: page.description().map(|d| d.into()),
+            page_path: page.page_path().into(),
+            permalink: sitemap
+                .get(&page.page_path().to_string_lossy())
+                .map(|entry| entry.permalink.clone())
+                .unwrap_or_default(),
+            date: page.date().copied(),
+            weight: page.weight(),
+        }
+    }
+}
+
+// Sorts by date newest first, by title, or by weight with the lowest first
+fn sort_section_pages(pages: &mut [SectionPage], sort_by: &str) {
+    let by_title = |a: &SectionPage, b: &SectionPage| {
+        a.title
+            .cmp(&b.title)
+            .then_with(|| a.page_path.cmp(&b.page_path))
+    };
+
+    pages.sort_by(|a, b| match sort_by {
+        "title" => by_title(a, b),
+        "weight" => match (a.weight, b.weight) {
+            (Some(a_weight), Some(b_weight)) => a_weight.cmp(&b_weight),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+        .then_with(|| by_title(a, b)),
+        _ => b.date.cmp(&a.date).then_with(|| by_title(a, b)),
+    });
+}
+
+#[derive(Debug, Serialize)]
+pub struct Section {
+    // Directory of the _index.org under the content dir, such as "/posts/2021"
+    pub path: PathBuf,
+    pub page: SectionPage,
+    pub sort_by: String,
+    pub parent: Option<PathBuf>,
+    pub pages: Vec<SectionPage>,
+    // Pages of the sections directly below, in the same order as the pages
+    pub subsections: Vec<SectionPage>,
+}
+
+// Every directory with an _index.org, keyed by the directory
+#[derive(Debug, Default, Serialize)]
+pub struct Sections(BTreeMap<PathBuf, Section>);
+
+impl Sections {
+    pub fn collect(pages: &HashMap<PathBuf, Page>, sitemap: &Sitemap) -> Sections {
+        let mut sections = BTreeMap::new();
+
+        for page in pages.values().filter(|page| page.is_section()) {
+            sections.insert(
+                page.file_path().to_path_buf(),
+                Section {
+                    path: page.file_path().into(),
+                    page: SectionPage::new(page, sitemap),
+                    sort_by: page.sort_by().unwrap_or("date").into(),
+                    parent: None,
+                    pages: Vec::new(),
+                    subsections: Vec::new(),
+                },
+            );
+        }
+
+        let mut sections = Sections(sections);
+
+        for page in pages.values() {
+            // Sections are children of the nearest section above their directory
+            let parent = match sections.parent_of(page.file_path()) {
+                Some(parent) => parent.path.clone(),
+                None => continue,
+            };
+
+            let section_page = SectionPage::new(page, sitemap);
+            let parent_section = sections.0.get_mut(&parent).unwrap();
+
+            if page.is_section() {
+                parent_section.subsections.push(section_page);
+                sections.0.get_mut(page.file_path()).unwrap().parent = Some(parent);
+            } else {
+                parent_section.pages.push(section_page);
+            }
+        }
+
+        for section in sections.0.values_mut() {
+            sort_section_pages(&mut section.pages, &section.sort_by);
+            sort_section_pages(&mut section.subsections, &section.sort_by);
+        }
+
+        sections
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&Section> {
+        self.0.get(path)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Section> {
+        self.0.values()
+    }
+
+    // The nearest section in a directory above the file path
+    pub fn parent_of(&self, file_path: &Path) -> Option<&Section> {
+        file_path
+            .ancestors()
+            .skip(1)
+            .find_map(|ancestor| self.0.get(ancestor))
+    }
+
+    // The section of a section page, otherwise the section the page is in
+    pub fn section_of(&self, page: &Page) -> Option<&Section> {
+        if page.is_section() {
+            self.0.get(page.file_path())
+        } else {
+            self.parent_of(page.file_path())
+        }
+    }
+
+    // The sections above a page starting at the root, followed by the page
+    pub fn breadcrumbs(&self, page: &Page, sitemap: &Sitemap) -> Vec<SectionPage> {
+        let mut breadcrumbs = vec![SectionPage::new(page, sitemap)];
+        let mut parent = self.parent_of(page.file_path());
+
+        while let Some(section) = parent {
+            breadcrumbs.push(section.page.clone());
+            parent = section.parent.as_ref().and_then(|path| self.0.get(path));
+        }
+
+        breadcrumbs.reverse();
+
+        breadcrumbs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SiteConfig;
+    use std::fs;
+
+    // Reads the pages of a content dir made from (path, text) pairs
+    fn read_pages(files: &[(&str, &str)]) -> HashMap<PathBuf, Page> {
+        let root = tempfile::tempdir().unwrap();
+        let config = SiteConfig::parse("[site]\nbase_url = \"https://example.com\"\n").unwrap();
+        let mut pages = HashMap::new();
+
+        for (path, text) in files {
+            let path = root.path().join("content").join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, text).unwrap();
+
+            let page = Page::read_file(root.path(), path, &config, None).unwrap();
+            pages.insert(page.page_path().into(), page);
+        }
+
+        pages
+    }
+
+    fn titles(pages: &[SectionPage]) -> Vec<&str> {
+        pages
+            .iter()
+            .map(|page| page.title.as_deref().unwrap_or_default())
+            .collect()
+    }
+
+    #[test]
+    fn sort_pages_by_date_title_and_weight() {
+        let pages = read_pages(&[
+            ("by-date/_index.org", "#+TITLE: By date\n"),
+            ("by-date/a.org", "#+TITLE: A\n#+DATE: <2021-01-01 Fri>\n"),
+            ("by-date/b.org", "#+TITLE: B\n#+DATE: <2021-03-01 Mon>\n"),
+            (
+                "by-title/_index.org",
+                "#+TITLE: By title\n#+SORT_BY: title\n",
+            ),
+            ("by-title/b.org", "#+TITLE: B\n"),
+            ("by-title/a.org", "#+TITLE: A\n"),
+            (
+                "by-weight/_index.org",
+                "#+TITLE: By weight\n#+SORT_BY: weight\n",
+            ),
+            ("by-weight/a.org", "#+TITLE: A\n"),
+            ("by-weight/b.org", "#+TITLE: B\n#+WEIGHT: 2\n"),
+            ("by-weight/c.org", "#+TITLE: C\n#+WEIGHT: 1\n"),
+        ]);
+        let sections = Sections::collect(&pages, &Sitemap::default());
+        let pages_of = |path: &str| titles(&sections.get(Path::new(path)).unwrap().pages);
+
+        assert_eq!(pages_of("/by-date"), ["B", "A"]);
+        assert_eq!(pages_of("/by-title"), ["A", "B"]);
+        assert_eq!(pages_of("/by-weight"), ["C", "B", "A"]);
+    }
+
+    #[test]
+    fn nested_sections_and_breadcrumbs() {
+        let pages = read_pages(&[
+            ("_index.org", "#+TITLE: Home\n"),
+            ("docs/_index.org", "#+TITLE: Docs\n"),
+            ("docs/guide/_index.org", "#+TITLE: Guide\n"),
+            ("docs/guide/install.org", "#+TITLE: Install\n"),
+            ("docs/faq.org", "#+TITLE: FAQ\n"),
+        ]);
+        let sections = Sections::collect(&pages, &Sitemap::default());
+
+        let docs = sections.get(Path::new("/docs")).unwrap();
+        assert_eq!(docs.parent.as_deref(), Some(Path::new("/")));
+        assert_eq!(titles(&docs.pages), ["FAQ"]);
+        assert_eq!(titles(&docs.subsections), ["Guide"]);
+
+        let install = &pages[Path::new("/docs/guide/install")];
+        assert_eq!(
+            sections.section_of(install).unwrap().path,
+            Path::new("/docs/guide")
+        );
+        assert_eq!(
+            titles(&sections.breadcrumbs(install, &Sitemap::default())),
+            ["Home", "Docs", "Guide", "Install"]
+        );
+    }
+}
@@ -1,28 +1,80 @@
 use crate::{
-    config::SiteConfig, highlighting::Highlighting, page::Page, posts::Posts, sitemap::Sitemap,
-    template_fns,
+    assets::AssetManifest,
+    cache::{self, BuildCache, CachedOutput, FileRead, FileReads},
+    compress,
+    config::SiteConfig,
+    diagnostics::{collect_diagnostics, Diagnostic, Diagnostics},
+    highlighting::Highlighting,
+    images::ImageProcessor,
+    minify,
+    org_emitter::EmitOptions,
+    page::{Page, PageCache},
+    posts::{ArchiveYear, Posts},
+    redirects::Redirects,
+    report::BuildReport,
+    search::SearchIndex,
+    sections::Sections,
+    sitemap::Sitemap,
+    taxonomies::{Taxonomies, Taxonomy, Term},
+    template_deps::TemplateDeps,
+    template_fns::{self, PageValues},
+    toc::Toc,
 };
 use anyhow::Result;
 use glob::glob;
+use serde_derive::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    fs,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    error::Error as StdError,
+    fmt, fs,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Instant,
 };
 use tera::Tera;
 use thiserror::Error;
 
+// A rendered document with what it left in the out dir, memoised by the build cache
+#[derive(Serialize, Deserialize)]
+struct RenderedDocument {
+    toc: Toc,
+    html: String,
+    // Rendered summary of a post
+    summary: Option<String>,
+    warnings: Vec<Diagnostic>,
+    // Resized images the document links to
+    files: Vec<String>,
+    // Images and included files read while rendering the document
+    #[serde(default)]
+    reads: Vec<FileRead>,
+}
+
 pub struct Site {
     site_config: SiteConfig,
     live_update: bool,
+    minify: bool,
+    cache: bool,
+    // Loaded with the content, so pages parsed before are not parsed again
+    build_cache: Option<Arc<BuildCache>>,
     out_path: PathBuf,
+    // Staging dir next to the out dir which the build renders into
+    build_path: PathBuf,
     root_path: PathBuf,
     tera: Tera,
     highlighting: Highlighting,
     highlighting_dark: Option<Highlighting>,
     pages: HashMap<PathBuf, Page>,
     posts: Posts,
+    // Overview of the posts by date given to every template
+    archives: Vec<ArchiveYear>,
+    redirects: Redirects,
+    taxonomies: Taxonomies,
+    sections: Sections,
     sitemap: Sitemap,
+    dependencies: template_fns::Dependencies,
+    // Files read since the last cached output was rendered
+    file_reads: Arc<FileReads>,
+    report: Mutex<BuildReport>,
 }
 
 impl Site {
@@ -60,25 +112,40 @@ impl Site {
 
         tera.build_inheritance_chains()?;
 
-        let highlighting = Highlighting::new(root_path, site_config.site.syntax_theme.clone())?;
-        let highlighting_dark =
-            if let Some(dark_theme) = site_config.site.syntax_theme_dark.as_ref() {
-                Some(Highlighting::new(root_path, Some(dark_theme.into()))?)
-            } else {
-                None
-            };
+        let mut highlighting = Highlighting::new(root_path, site_config.site.syntax_theme.clone())?;
+        highlighting.set_classed(site_config.site.syntax_classes);
+        highlighting.set_aliases(site_config.syntax_aliases.clone());
+
+        let highlighting_dark = site_config
+            .site
+            .syntax_theme_dark
+            .as_ref()
+            .map(|dark_theme| highlighting.with_theme(Some(dark_theme.into())));
+
+        let build_path = sibling_dir(&out_path, "staging");
 
         Ok(Site {
             root_path: root_path.into(),
             out_path,
+            build_path,
             site_config,
             live_update: false,
+            minify: true,
+            cache: true,
+            build_cache: None,
             sitemap: Sitemap::default(),
             highlighting,
             highlighting_dark,
             tera,
             pages: HashMap::new(),
             posts: Posts::default(),
+            archives: Vec::new(),
+            redirects: Redirects::default(),
+            taxonomies: Taxonomies::default(),
+            sections: Sections::default(),
+            dependencies: Default::default(),
+            file_reads: Default::default(),
+            report: Default::default(),
         })
     }
 
@@ -90,43 +157,146 @@ impl Site {
         self.live_update = live_update;
     }
 
+    // Allows turning off the configured minification, such as when serving
+    pub fn set_minify(&mut self, minify: bool) {
+        self.minify = minify;
+    }
+
+    // Allows rendering every output without reading or writing the build cache
+    pub fn set_cache(&mut self, cache: bool) {
+        self.cache = cache;
+    }
+
+    // Data files read by templates during the last build
+    pub fn dependencies(&self) -> Vec<PathBuf> {
+        self.dependencies.lock().unwrap().iter().cloned().collect()
+    }
+
+    // What the last build did, complete once the build has succeeded
+    pub fn report(&self) -> MutexGuard<'_, BuildReport> {
+        self.report.lock().unwrap()
+    }
+
+    // Aliases of the pages, filled in by load_content
+    pub fn redirects(&self) -> &Redirects {
+        &self.redirects
+    }
+
     pub fn load_content(&mut self) -> Result<()> {
         let content_dir = self.root_path.join("content");
 
-        let pages: Vec<Page> = glob(&format!("{}/**/*.org", content_dir.to_string_lossy()))?
-            .filter_map(|p| p.ok())
-            .filter(|e| {
-                !e.as_path()
-                    .file_name()
-                    .unwrap()
-                    .to_string_lossy()
-                    .starts_with('.')
-            })
-            .map(|page_path| Page::read_file(&self.root_path, page_path, &self.site_config))
-            .collect::<Result<Vec<Page>>>()?;
+        let page_paths: Vec<PathBuf> =
+            glob(&format!("{}/**/*.org", content_dir.to_string_lossy()))?
+                .filter_map(|p| p.ok())
+                .filter(|e| {
+                    !e.as_path()
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .starts_with('.')
+                })
+                .collect();
+
+        self.build_cache = if self.cache {
+            Some(Arc::new(BuildCache::load(&self.root_path)?))
+        } else {
+            None
+        };
+
+        let page_cache = match &self.build_cache {
+            Some(cache) => Some(PageCache::new(cache, &self.site_config)?),
+            None => None,
+        };
+
+        // Diagnostics of every page are reported together
+        let mut diagnostics = Vec::new();
+        let mut pages = Vec::new();
+
+        for page_path in page_paths {
+            let result = Page::read_file(
+                &self.root_path,
+                page_path,
+                &self.site_config,
+                page_cache.as_ref(),
+            );
+
+            if let Some(page) = collect_diagnostics(&mut diagnostics, result)? {
+                pages.push(page);
+            }
+        }
 
         let mut post_paths: Vec<PathBuf> = Vec::new();
 
+        // Sections are decided by the files, as the page paths may be custom permalinks
         for page in &pages {
             if page.ty() == Some("posts") {
-                post_paths.push(page.page_path().into());
+                post_paths.push(page.file_path().into());
             }
         }
 
         for page in pages {
+            if let Some(other) = self.pages.get(page.page_path()) {
+                diagnostics.push(
+                    Diagnostic::error(
+                        SiteError::PagePathCollision(
+                            page.page_path().to_string_lossy().into(),
+                            other.diagnostic_file().into(),
+                        )
+                        .to_string(),
+                        page.diagnostic_file(),
+                    )
+                    .with_help("give one of the pages another #+SLUG or #+PERMALINK"),
+                );
+
+                continue;
+            }
+
             self.sitemap.add_page(&self.site_config, &page)?;
 
-            if let Some(parent) = page.page_path().parent() {
-                if post_paths.contains(&parent.into()) {
-                    self.posts.add_page(&page)?;
-                }
+            // Posts may be in subsections of a posts section, but section pages are not posts
+            let is_post = !page.is_section()
+                && page
+                    .file_path()
+                    .ancestors()
+                    .skip(1)
+                    .any(|ancestor| post_paths.iter().any(|path| path == ancestor));
+
+            if is_post {
+                collect_diagnostics(&mut diagnostics, self.posts.add_page(&page))?;
             }
 
             self.pages.insert(page.page_path().into(), page);
         }
 
+        let taxonomies = Taxonomies::collect(&self.pages, &self.sitemap, &self.site_config);
+
+        if let Some(taxonomies) = collect_diagnostics(&mut diagnostics, taxonomies)? {
+            self.taxonomies = taxonomies;
+        }
+
+        // Archives need the posts newest first
         self.posts.sort();
-        self.posts.generate_tag_index();
+        self.archives = self.posts.archives(
+            &self.site_config.site.base_url,
+            &self.site_config.archives.path,
+        );
+
+        // Aliases may not overwrite any page, including the listings
+        let redirects = Redirects::collect(&self.pages, &self.generated_paths());
+
+        if let Some(redirects) = collect_diagnostics(&mut diagnostics, redirects)? {
+            self.redirects = redirects;
+        }
+
+        if !diagnostics.is_empty() {
+            return Err(Diagnostics(diagnostics).into());
+        }
+
+        self.posts.generate_tag_index(&self.taxonomies);
+        self.posts.generate_series_index();
+        self.posts.generate_related(&self.site_config.related);
+
+        self.sections = Sections::collect(&self.pages, &self.sitemap);
 
         Ok(())
     }
@@ -142,7 +312,7 @@ impl Site {
             }
 
             let stripped_path = scss_path.strip_prefix(path).unwrap();
-            let out_path = self.out_path.join(stripped_path).with_extension("css");
+            let out_path = self.build_path.join(stripped_path).with_extension("css");
 
             let css_output = sass_rs::compile_file(
                 &scss_path,
@@ -161,18 +331,50 @@ impl Site {
     }
 
     pub fn build(&mut self) -> Result<()> {
+        self.dependencies.lock().unwrap().clear();
         self.tera.register_function(
-            "get_url",
-            template_fns::GetUrl::new(self.site_config.clone(), self.out_path.clone()),
+            "load_data",
+            template_fns::LoadData::new(
+                self.root_path.join("data"),
+                self.dependencies.clone(),
+                self.file_reads.clone(),
+            ),
         );
 
-        // Delete the previous out path if exists
-        if self.out_path.exists() {
-            fs::remove_dir_all(&self.out_path)?;
+        // Delete a staging dir left behind by an interrupted build
+        if self.build_path.exists() {
+            fs::remove_dir_all(&self.build_path)?;
+        }
+
+        fs::create_dir_all(&self.build_path)?;
+
+        let started = Instant::now();
+
+        *self.report() = BuildReport {
+            pages: self.pages.len(),
+            posts: self.posts.posts().len(),
+            tags: self.posts.tags().len(),
+            ..Default::default()
+        };
+
+        // The previous output stays in place until the build succeeds
+        if let Err(err) = self.build_staged() {
+            fs::remove_dir_all(&self.build_path)?;
+
+            return Err(err);
         }
 
-        // Create the out path
-        fs::create_dir_all(&self.out_path)?;
+        self.swap_out_dir()?;
+
+        let mut report = self.report();
+        report.add_files(&self.out_path)?;
+        report.duration_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+        Ok(())
+    }
+
+    fn build_staged(&mut self) -> Result<()> {
+        let mut started = Instant::now();
 
         // Build the themes styles
         if let Some(theme) = &self.site_config.site.theme {
@@ -183,11 +385,120 @@ impl Site {
             }
         }
 
+        // Write the stylesheets for class based highlighting
+        if self.highlighting.classed() {
+            self.render_syntax_css()?;
+        }
+
+        self.end_phase("styles", &mut started);
+
+        // Assets are in place before rendering so get_url does not depend on render order
+        self.merge_static()?;
+        self.copy_files()?;
+        self.copy_resources()?;
+
+        if self.minify {
+            minify::minify_assets(&self.build_path, &self.site_config.minify)?;
+        }
+
+        self.build_assets()?;
+
+        // Rendered outputs may not replace these, static files used to win over pages
+        let static_files = relative_files(&self.build_path)?;
+
+        let cache = self.build_cache.clone();
+
+        self.end_phase("assets", &mut started);
+
+        let images = Arc::new(ImageProcessor::new(
+            &self.root_path,
+            &self.build_path,
+            &self.site_config.site.base_url,
+            self.site_config.images.clone(),
+            self.file_reads.clone(),
+        ));
+
+        self.tera.register_function(
+            "resize_image",
+            template_fns::ResizeImage::new(images.clone()),
+        );
+
+        let documents_hash = match cache {
+            Some(_) => self.documents_hash()?,
+            None => String::new(),
+        };
+
+        // Render the documents first so templates can look up any page
+        let mut documents = HashMap::new();
+        let mut summaries = HashMap::new();
+        let post_paths: HashSet<PathBuf> = self
+            .posts
+            .posts()
+            .iter()
+            .map(|post| post.page_path().to_path_buf())
+            .collect();
+
+        for (page_path, page) in &self.pages {
+            let is_post = post_paths.contains(page_path);
+            let document = match &cache {
+                Some(cache) => {
+                    self.render_document_cached(cache, &documents_hash, &images, page, is_post)?
+                }
+                None => self.render_document(page, &images, None, is_post)?,
+            };
+
+            self.report().warnings.extend(document.warnings);
+
+            if let Some(summary) = document.summary {
+                summaries.insert(page_path.clone(), summary);
+            }
+
+            documents.insert(page_path.clone(), (document.toc, document.html));
+        }
+
+        self.posts.set_summaries(summaries);
+
+        let page_values = self.register_page_functions(&documents)?;
+        self.end_phase("documents", &mut started);
+
+        let mut template_errors = Vec::new();
+
         // Render pages
-        for (_, page) in &self.pages {
-            self.render_page(page)?;
+        match &cache {
+            Some(cache) => {
+                let templates = self.page_templates()?;
+
+                for (page_path, page) in &self.pages {
+                    let (toc, html) = &documents[page_path];
+
+                    collect_template_error(
+                        &mut template_errors,
+                        self.render_page_cached(
+                            cache,
+                            &templates,
+                            &images,
+                            &page_values,
+                            page,
+                            toc,
+                            html,
+                        ),
+                    )?;
+                }
+            }
+            None => {
+                for (page_path, page) in &self.pages {
+                    let (toc, html) = &documents[page_path];
+
+                    collect_template_error(
+                        &mut template_errors,
+                        self.render_page(page, toc, html),
+                    )?;
+                }
+            }
         }
 
+        self.end_phase("pages", &mut started);
+
         let last_update = self.posts.last_update();
 
         // Render extras
@@ -197,18 +508,529 @@ impl Site {
         tera_context.insert("posts", &self.posts);
         tera_context.insert("last_update", &last_update);
 
-        self.render_404(&tera_context)?;
-        self.render_robots(&tera_context)?;
-        self.render_sitemap(&tera_context)?;
-        self.merge_static()?;
-        self.copy_files()?;
+        collect_template_error(&mut template_errors, self.render_404(&tera_context))?;
+        collect_template_error(&mut template_errors, self.render_robots(&tera_context))?;
+        collect_template_error(&mut template_errors, self.render_sitemap(&tera_context))?;
 
         if self.site_config.feeds.atom {
-            self.render_atom(&tera_context)?;
+            collect_template_error(&mut template_errors, self.render_atom(&tera_context))?;
         }
 
         if self.site_config.feeds.rss {
-            self.render_rss(&tera_context)?;
+            collect_template_error(&mut template_errors, self.render_rss(&tera_context))?;
+        }
+
+        if self.site_config.archives.enabled {
+            for archive_year in &self.archives {
+                collect_template_error(
+                    &mut template_errors,
+                    self.render_archive(&tera_context, &archive_year.path, archive_year.year, None),
+                )?;
+
+                if self.site_config.archives.months {
+                    for archive_month in &archive_year.months {
+                        collect_template_error(
+                            &mut template_errors,
+                            self.render_archive(
+                                &tera_context,
+                                &archive_month.path,
+                                archive_year.year,
+                                Some(archive_month.month),
+                            ),
+                        )?;
+                    }
+                }
+            }
+        }
+
+        for taxonomy in self.taxonomies.iter() {
+            if taxonomy.config.render {
+                collect_template_error(
+                    &mut template_errors,
+                    self.render_taxonomy(&tera_context, taxonomy),
+                )?;
+            }
+        }
+
+        if !template_errors.is_empty() {
+            return Err(SiteError::Templates(TemplateReport(template_errors)).into());
+        }
+
+        self.render_redirects()?;
+
+        if self.site_config.search.enabled {
+            SearchIndex::build(&self.pages, &self.sitemap, &self.site_config.search)?
+                .write(&self.build_path)?;
+        }
+
+        if let Some(output) = self
+            .report()
+            .outputs
+            .iter()
+            .find(|output| static_files.contains(&output.path))
+        {
+            return Err(SiteError::StaticCollision(output.path.clone()).into());
+        }
+
+        self.end_phase("extras", &mut started);
+
+        // Compress last so every output has its variants
+        compress::compress_outputs(&self.build_path, &self.site_config.compress)?;
+        self.end_phase("compress", &mut started);
+
+        if let Some(cache) = &cache {
+            cache.save()?;
+        }
+
+        Ok(())
+    }
+
+    fn end_phase(&self, name: &str, started: &mut Instant) {
+        self.report().add_phase(name, started.elapsed());
+        *started = Instant::now();
+    }
+
+    // Hash of the inputs shared by every document, documents do not depend on each other
+    fn documents_hash(&self) -> Result<String> {
+        let dark_key = self
+            .highlighting_dark
+            .as_ref()
+            .map(|highlighting| highlighting.cache_key())
+            .unwrap_or_default();
+
+        Ok(cache::hash_parts(&[
+            env!("CARGO_PKG_VERSION"),
+            &cache::hash_value(&self.site_config)?,
+            &self.highlighting.cache_key(),
+            &dark_key,
+        ]))
+    }
+
+    // Hash of the settings and the template files of every page template, with what the
+    // templates read, so a page only depends on the templates it is rendered with
+    fn page_templates(&self) -> Result<HashMap<&str, (String, TemplateDeps)>> {
+        let settings = [
+            env!("CARGO_PKG_VERSION").to_string(),
+            cache::hash_value(&self.site_config)?,
+            format!("{} {}", self.live_update, self.minify),
+        ];
+        let mut templates = HashMap::new();
+
+        for page in self.pages.values() {
+            let name = page_template(page);
+
+            if templates.contains_key(name) {
+                continue;
+            }
+
+            let deps = TemplateDeps::collect(&self.tera, name);
+            let mut parts = settings.to_vec();
+
+            for template_name in &deps.templates {
+                let path = self.tera.templates[template_name].path.as_ref();
+
+                parts.push(template_name.clone());
+                parts.push(
+                    path.map(|path| cache::hash_file(Path::new(path)))
+                        .unwrap_or_default(),
+                );
+            }
+
+            let parts: Vec<&str> = parts.iter().map(|part| part.as_str()).collect();
+
+            templates.insert(name, (cache::hash_parts(&parts), deps));
+        }
+
+        Ok(templates)
+    }
+
+    // Hash of the context variables read by the templates of an output
+    fn context_hash(deps: &TemplateDeps, context: &tera::Context) -> Result<String> {
+        if deps.whole_context {
+            return cache::hash_value(&context.clone().into_json());
+        }
+
+        let mut parts = Vec::new();
+
+        for variable in &deps.variables {
+            parts.push(variable.clone());
+            parts.push(cache::hash_value(&context.get(variable))?);
+        }
+
+        let parts: Vec<&str> = parts.iter().map(|part| part.as_str()).collect();
+
+        Ok(cache::hash_parts(&parts))
+    }
+
+    // Hash of an output and the pages its templates looked up
+    fn output_hash(base_hash: &str, page_values: &PageValues, page_reads: &[String]) -> String {
+        let mut parts = vec![base_hash];
+
+        for page_path in page_reads {
+            parts.push(page_path);
+            parts.push(page_values.value_hash(page_path));
+        }
+
+        cache::hash_parts(&parts)
+    }
+
+    // Renders a page unless the previous build rendered it from the same inputs
+    #[allow(clippy::too_many_arguments)]
+    fn render_page_cached(
+        &self,
+        cache: &BuildCache,
+        templates: &HashMap<&str, (String, TemplateDeps)>,
+        images: &ImageProcessor,
+        page_values: &PageValues,
+        page: &Page,
+        toc: &Toc,
+        html: &str,
+    ) -> Result<()> {
+        let output: String = page.page_path().join("index.html").to_string_lossy().into();
+        let template = page_template(page);
+        let (template_hash, deps) = &templates[template];
+
+        let mut context = self.page_context(page, toc, html);
+        self.add_template_values(template, &mut context);
+
+        let base_hash = cache::hash_parts(&[
+            template_hash,
+            &cache::hash_value(page)?,
+            &Site::context_hash(deps, &context)?,
+        ]);
+
+        if let Some(cached_output) = cache.previous_output(&output).filter(|cached_output| {
+            cached_output.hash == Site::output_hash(&base_hash, page_values, &cached_output.pages)
+                && cache.files_unchanged(&cached_output.reads)
+        }) {
+            if self.reuse_output(&output, &cached_output.files)? {
+                cache.insert_output(&output, cached_output.clone());
+
+                let mut report = self.report();
+                report.cached_pages += 1;
+                report.add_output(&output, page_source(page), Some(page_template(page)));
+
+                return Ok(());
+            }
+        }
+
+        images.take_used();
+        page_values.take_reads();
+        self.file_reads.take();
+        self.write_page(page, &context)?;
+
+        let pages = page_values.take_reads();
+
+        cache.insert_output(
+            &output,
+            CachedOutput {
+                hash: Site::output_hash(&base_hash, page_values, &pages),
+                files: images.take_used(),
+                pages,
+                reads: self.file_reads.take(),
+            },
+        );
+
+        Ok(())
+    }
+
+    // Copies an output and its files from the previous out dir, if they are all still there
+    fn reuse_output(&self, output: &str, files: &[String]) -> Result<bool> {
+        self.reuse_files(std::iter::once(output).chain(files.iter().map(|file| file.as_str())))
+    }
+
+    fn reuse_files<'a>(&self, files: impl Iterator<Item = &'a str>) -> Result<bool> {
+        let paths: Vec<&str> = files.map(|path| path.trim_start_matches('/')).collect();
+
+        if !paths.iter().all(|path| self.out_path.join(path).is_file()) {
+            return Ok(false);
+        }
+
+        for path in paths {
+            let build_file = self.build_path.join(path);
+
+            if build_file.is_file() {
+                continue;
+            }
+
+            if let Some(parent) = build_file.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            fs::copy(self.out_path.join(path), build_file)?;
+        }
+
+        Ok(true)
+    }
+
+    // Move the staging dir into place, the old output is removed only after the swap
+    fn swap_out_dir(&self) -> Result<()> {
+        if !self.out_path.exists() {
+            fs::rename(&self.build_path, &self.out_path)?;
+
+            return Ok(());
+        }
+
+        // The staging dir holds the previous output after the exchange
+        exchange_dirs(&self.build_path, &self.out_path)?;
+
+        // The new output is in place, so a leftover dir is only reported
+        if let Err(err) = fs::remove_dir_all(&self.build_path) {
+            self.report().warnings.push(Diagnostic::warning(
+                format!("could not remove the previous output: {}", err),
+                &self.build_path,
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Fingerprint the assets and register the asset template functions
+    fn build_assets(&mut self) -> Result<()> {
+        let manifest = AssetManifest::build(&self.build_path, &self.site_config.assets)?;
+
+        if self.site_config.assets.fingerprint || self.site_config.assets.integrity {
+            manifest.write(&self.build_path.join("asset-manifest.json"))?;
+        }
+
+        let manifest = Arc::new(manifest);
+
+        self.tera.register_function(
+            "get_url",
+            template_fns::GetUrl::new(
+                self.site_config.clone(),
+                self.build_path.clone(),
+                manifest.clone(),
+                self.file_reads.clone(),
+            ),
+        );
+        self.tera.register_function(
+            "get_integrity",
+            template_fns::GetIntegrity::new(
+                self.build_path.clone(),
+                manifest,
+                self.file_reads.clone(),
+            ),
+        );
+
+        Ok(())
+    }
+
+    // Lists the posts of a year or month with the archive.html template
+    fn render_archive(
+        &self,
+        context: &tera::Context,
+        archive_path: &str,
+        year: i32,
+        month: Option<u32>,
+    ) -> Result<()> {
+        let page_path = Path::new(archive_path.trim_end_matches('/'));
+
+        if self.pages.contains_key(page_path) {
+            return Err(SiteError::ArchiveCollision(archive_path.into()).into());
+        }
+
+        let mut context = context.clone();
+        context.insert("archive_year", &year);
+        context.insert("archive_month", &month);
+        context.insert("archive_posts", &self.posts.in_archive(year, month));
+
+        let contents = self
+            .render_template("archive.html", &context)
+            .map_err(|e| e.with_page(page_path))?;
+
+        let out_path = self.build_path.join(archive_path.trim_matches('/'));
+
+        fs::create_dir_all(&out_path)?;
+        fs::write(out_path.join("index.html"), contents)?;
+
+        self.report().add_output(
+            &format!("{}index.html", archive_path),
+            None,
+            Some("archive.html"),
+        );
+
+        Ok(())
+    }
+
+    // Paths written besides the content pages, without a trailing slash
+    fn generated_paths(&self) -> BTreeSet<String> {
+        let mut paths: BTreeSet<String> = ["/404.html", "/robots.txt", "/sitemap.xml"]
+            .iter()
+            .map(|path| path.to_string())
+            .collect();
+
+        let mut feeds = Vec::new();
+        if self.site_config.feeds.atom {
+            feeds.push("atom.xml");
+        }
+        if self.site_config.feeds.rss {
+            feeds.push("rss.xml");
+        }
+
+        for feed in &feeds {
+            paths.insert(format!("/{}", feed));
+        }
+
+        if self.site_config.archives.enabled {
+            for archive_year in &self.archives {
+                paths.insert(archive_year.path.clone());
+
+                if self.site_config.archives.months {
+                    for archive_month in &archive_year.months {
+                        paths.insert(archive_month.path.clone());
+                    }
+                }
+            }
+        }
+
+        for taxonomy in self.taxonomies.iter().filter(|t| t.config.render) {
+            paths.insert(taxonomy.path.clone());
+
+            for term in &taxonomy.terms {
+                let pager_count = term.pages.chunks(taxonomy.per_page(term)).count().max(1);
+
+                for number in 1..=pager_count {
+                    paths.insert(term.pager_path(number));
+                }
+
+                if taxonomy.config.feeds {
+                    for feed in &feeds {
+                        paths.insert(format!("{}{}", term.path, feed));
+                    }
+                }
+            }
+        }
+
+        paths
+            .into_iter()
+            .map(|path| path.trim_end_matches('/').to_string())
+            .collect()
+    }
+
+    fn is_archive_path(&self, path: &str) -> bool {
+        let path = path.trim_end_matches('/');
+        let is_path = |archive_path: &str| archive_path.trim_end_matches('/') == path;
+
+        self.site_config.archives.enabled
+            && self.archives.iter().any(|archive_year| {
+                is_path(&archive_year.path)
+                    || (self.site_config.archives.months
+                        && archive_year
+                            .months
+                            .iter()
+                            .any(|archive_month| is_path(&archive_month.path)))
+            })
+    }
+
+    // Writes a page to a path such as "/tags/rust/" which no page, archive or alias may use
+    fn write_listing(
+        &self,
+        path: &str,
+        template: &str,
+        context: &tera::Context,
+        file_name: &str,
+    ) -> Result<()> {
+        let page_path = Path::new(path.trim_end_matches('/'));
+
+        if self.pages.contains_key(page_path) {
+            return Err(SiteError::TaxonomyCollision(path.into(), "a page").into());
+        }
+
+        if self.is_archive_path(path) {
+            return Err(SiteError::TaxonomyCollision(path.into(), "an archive").into());
+        }
+
+        if self.redirects.get(path).is_some() {
+            return Err(SiteError::TaxonomyCollision(path.into(), "an alias").into());
+        }
+
+        let contents = self
+            .render_template(template, context)
+            .map_err(|e| e.with_page(page_path))?;
+
+        let out_path = self.build_path.join(path.trim_matches('/'));
+
+        fs::create_dir_all(&out_path)?;
+        fs::write(out_path.join(file_name), contents)?;
+
+        self.report()
+            .add_output(&format!("{}{}", path, file_name), None, Some(template));
+
+        Ok(())
+    }
+
+    // Lists the terms of a taxonomy and writes the pages and feeds of every term
+    fn render_taxonomy(&self, context: &tera::Context, taxonomy: &Taxonomy) -> Result<()> {
+        let mut context = context.clone();
+        context.insert("taxonomy", taxonomy);
+
+        self.write_listing(
+            &taxonomy.path,
+            &taxonomy.config.template,
+            &context,
+            "index.html",
+        )?;
+
+        for term in &taxonomy.terms {
+            self.render_term(&context, taxonomy, term)?;
+        }
+
+        Ok(())
+    }
+
+    fn render_term(&self, context: &tera::Context, taxonomy: &Taxonomy, term: &Term) -> Result<()> {
+        let mut context = context.clone();
+        context.insert("term", term);
+
+        let chunks: Vec<_> = term.pages.chunks(taxonomy.per_page(term)).collect();
+
+        for (i, pages) in chunks.iter().enumerate() {
+            let number = i + 1;
+
+            let mut paginator = tera::Map::new();
+            paginator.insert("current".into(), number.into());
+            paginator.insert("total".into(), chunks.len().into());
+            paginator.insert("pages".into(), tera::to_value(pages)?);
+            paginator.insert(
+                "prev".into(),
+                tera::to_value((number > 1).then(|| term.pager_path(number - 1)))?,
+            );
+            paginator.insert(
+                "next".into(),
+                tera::to_value((number < chunks.len()).then(|| term.pager_path(number + 1)))?,
+            );
+
+            context.insert("paginator", &paginator);
+
+            self.write_listing(
+                &term.pager_path(number),
+                &taxonomy.config.term_template,
+                &context,
+                "index.html",
+            )?;
+        }
+
+        if taxonomy.config.feeds {
+            let page_paths: Vec<PathBuf> = term
+                .pages
+                .iter()
+                .map(|page| page.page_path.clone())
+                .collect();
+            let posts = self.posts.subset(&page_paths);
+
+            context.insert("posts", &posts);
+            context.insert("last_update", &posts.last_update());
+
+            if self.site_config.feeds.atom {
+                context.insert("feed_path", &format!("{}atom.xml", term.path));
+                self.write_listing(&term.path, "atom.xml", &context, "atom.xml")?;
+            }
+
+            if self.site_config.feeds.rss {
+                context.insert("feed_path", &format!("{}rss.xml", term.path));
+                self.write_listing(&term.path, "rss.xml", &context, "rss.xml")?;
+            }
         }
 
         Ok(())
@@ -216,67 +1038,291 @@ impl Site {
 
     pub fn render_404(&mut self, context: &tera::Context) -> Result<()> {
         let contents = self.render_template("404.html", context)?;
-        fs::write(self.out_path.join("404.html"), contents)?;
+        fs::write(self.build_path.join("404.html"), contents)?;
+        self.report().add_output("404.html", None, Some("404.html"));
 
         Ok(())
     }
 
     pub fn render_robots(&mut self, context: &tera::Context) -> Result<()> {
         let contents = self.render_template("robots.txt", context)?;
-        fs::write(self.out_path.join("robots.txt"), contents)?;
+        fs::write(self.build_path.join("robots.txt"), contents)?;
+        self.report()
+            .add_output("robots.txt", None, Some("robots.txt"));
 
         Ok(())
     }
 
     pub fn render_sitemap(&mut self, context: &tera::Context) -> Result<()> {
         let contents = self.render_template("sitemap.xml", context)?;
-        fs::write(self.out_path.join("sitemap.xml"), contents)?;
+        fs::write(self.build_path.join("sitemap.xml"), contents)?;
+        self.report()
+            .add_output("sitemap.xml", None, Some("sitemap.xml"));
 
         Ok(())
     }
 
     pub fn render_atom(&mut self, context: &tera::Context) -> Result<()> {
         let contents = self.render_template("atom.xml", context)?;
-        fs::write(self.out_path.join("atom.xml"), contents)?;
+        fs::write(self.build_path.join("atom.xml"), contents)?;
+        self.report().add_output("atom.xml", None, Some("atom.xml"));
 
         Ok(())
     }
 
     pub fn render_rss(&mut self, context: &tera::Context) -> Result<()> {
         let contents = self.render_template("rss.xml", context)?;
-        fs::write(self.out_path.join("rss.xml"), contents)?;
+        fs::write(self.build_path.join("rss.xml"), contents)?;
+        self.report().add_output("rss.xml", None, Some("rss.xml"));
 
         Ok(())
     }
 
-    pub fn render_page(&self, page: &Page) -> Result<()> {
-        let mut tera_context = tera::Context::new();
+    pub fn render_redirects(&mut self) -> Result<()> {
+        self.redirects
+            .write(&self.build_path, &self.site_config.site.base_url)?;
 
-        let (toc, html) = page.render_html(
-            &self.site_config.site.base_url,
-            &self.highlighting,
-            self.highlighting_dark.as_ref(),
+        for (alias, _) in self.redirects.iter() {
+            self.report()
+                .add_output(&format!("{}/index.html", alias), None, None);
+        }
+
+        if self.site_config.redirects.redirects_file {
+            fs::write(
+                self.build_path.join("_redirects"),
+                self.redirects.redirects_file(),
+            )?;
+        }
+
+        if self.site_config.redirects.nginx_map {
+            fs::write(
+                self.build_path.join("redirects.map"),
+                self.redirects.nginx_map(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn render_syntax_css(&mut self) -> Result<()> {
+        fs::write(
+            self.build_path.join("syntax-light.css"),
+            self.highlighting.theme_css()?,
         )?;
 
+        if let Some(highlighting_dark) = &self.highlighting_dark {
+            fs::write(
+                self.build_path.join("syntax-dark.css"),
+                highlighting_dark.theme_css()?,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn emit_options<'a>(
+        &'a self,
+        page: &'a Page,
+        images: &'a ImageProcessor,
+        cache: Option<&'a BuildCache>,
+    ) -> EmitOptions<'a> {
+        EmitOptions {
+            base_url: &self.site_config.site.base_url,
+            highlighting: &self.highlighting,
+            highlighting_dark: self.highlighting_dark.as_ref(),
+            images,
+            cache,
+            resources: page.resources(),
+        }
+    }
+
+    // Renders the document of a page, and the summary of a post
+    fn render_document(
+        &self,
+        page: &Page,
+        images: &ImageProcessor,
+        cache: Option<&BuildCache>,
+        is_post: bool,
+    ) -> Result<RenderedDocument> {
+        let options = self.emit_options(page, images, cache);
+        let (toc, html, warnings) = page.render_html(&options)?;
+
+        let summary = if is_post {
+            Some(page.render_summary_html(&options)?)
+        } else {
+            None
+        };
+
+        Ok(RenderedDocument {
+            toc,
+            html,
+            summary,
+            warnings: warnings
+                .iter()
+                .map(|warning| page.warning_diagnostic(warning))
+                .collect(),
+            files: Vec::new(),
+            reads: Vec::new(),
+        })
+    }
+
+    // Renders a document unless an earlier build rendered the same org text and resources,
+    // and the images and included files it read are unchanged
+    fn render_document_cached(
+        &self,
+        cache: &BuildCache,
+        documents_hash: &str,
+        images: &ImageProcessor,
+        page: &Page,
+        is_post: bool,
+    ) -> Result<RenderedDocument> {
+        let hash = cache::hash_parts(&[
+            documents_hash,
+            &page.page_path().to_string_lossy(),
+            &is_post.to_string(),
+            &cache::hash_value(&page.resources())?,
+            page.source(),
+        ]);
+
+        if let Some(document) = cache
+            .memoized::<RenderedDocument>("documents", &hash)
+            .filter(|document| cache.files_unchanged(&document.reads))
+        {
+            if self.reuse_files(document.files.iter().map(|file| file.as_str()))? {
+                return Ok(document);
+            }
+        }
+
+        images.take_used();
+        self.file_reads.take();
+
+        let mut document = self.render_document(page, images, Some(cache), is_post)?;
+
+        for include in page.includes() {
+            self.file_reads
+                .insert(&include, &cache::hash_file(&include));
+        }
+
+        document.files = images.take_used();
+        document.reads = self.file_reads.take();
+        cache.memoize("documents", &hash, &document)?;
+
+        Ok(document)
+    }
+
+    fn register_page_functions(
+        &mut self,
+        documents: &HashMap<PathBuf, (Toc, String)>,
+    ) -> Result<Arc<PageValues>> {
+        let mut page_values = BTreeMap::new();
+
+        for (page_path, page) in &self.pages {
+            let path_str: String = page_path.to_string_lossy().into();
+            let (toc, html) = &documents[page_path];
+            let permalink = self
+                .sitemap
+                .get(&path_str)
+                .map(|entry| entry.permalink.as_str())
+                .unwrap_or_default();
+
+            page_values.insert(
+                path_str,
+                template_fns::PageValue::new(page, permalink, toc, html)?,
+            );
+        }
+
+        let page_values = Arc::new(PageValues::new(page_values));
+
+        self.tera
+            .register_function("get_page", template_fns::GetPage::new(page_values.clone()));
+        self.tera.register_function(
+            "get_section",
+            template_fns::GetSection::new(page_values.clone(), &self.sections),
+        );
+        self.tera.register_function(
+            "get_taxonomy",
+            template_fns::GetTaxonomy::new(page_values.clone(), &self.taxonomies),
+        );
+
+        Ok(page_values)
+    }
+
+    pub fn render_page(&self, page: &Page, toc: &Toc, html: &str) -> Result<()> {
+        self.write_page(page, &self.page_context(page, toc, html))
+    }
+
+    fn page_context(&self, page: &Page, toc: &Toc, html: &str) -> tera::Context {
+        let mut tera_context = tera::Context::new();
+
         tera_context.insert("site_config", &self.site_config);
         tera_context.insert("posts", &self.posts);
         tera_context.insert("page", &page);
         tera_context.insert("document", &html);
         tera_context.insert("toc", &toc);
 
-        let contents =
-            self.render_template(page.template().unwrap_or("page.html"), &tera_context)?;
+        let (prev_post, next_post) = self.posts.navigation(page.page_path());
+        tera_context.insert("prev_post", &prev_post);
+        tera_context.insert("next_post", &next_post);
+        tera_context.insert("series", &self.posts.series_context(page.page_path()));
+        tera_context.insert("related", self.posts.related(page.page_path()));
+        tera_context.insert("terms", &self.taxonomies.page_terms(page));
+        tera_context.insert("section", &self.sections.section_of(page));
+        tera_context.insert(
+            "breadcrumbs",
+            &self.sections.breadcrumbs(page, &self.sitemap),
+        );
+
+        tera_context
+    }
+
+    fn write_page(&self, page: &Page, tera_context: &tera::Context) -> Result<()> {
+        let template = page_template(page);
+        let contents = self
+            .render_template(template, tera_context)
+            .map_err(|e| e.with_page(page.page_path()))?;
 
-        let out_path = self.out_path.join(page.page_path().strip_prefix("/")?);
+        let out_path = self.build_path.join(page.page_path().strip_prefix("/")?);
 
         fs::create_dir_all(&out_path)?;
         fs::write(out_path.join("index.html"), contents)?;
 
+        self.report().add_output(
+            &page.page_path().join("index.html").to_string_lossy(),
+            page_source(page),
+            Some(template),
+        );
+
         Ok(())
     }
 
-    fn render_template(&self, name: &str, context: &tera::Context) -> Result<String> {
+    fn render_template(
+        &self,
+        name: &str,
+        context: &tera::Context,
+    ) -> Result<String, TemplateError> {
         let mut context = context.clone();
+        self.add_template_values(name, &mut context);
+
+        let contents = self.tera.render(name, &context).map_err(|e| {
+            let template_path = self
+                .tera
+                .templates
+                .get(name)
+                .and_then(|template| template.path.clone());
+
+            TemplateError::new(name, template_path, &e)
+        })?;
+
+        if name.ends_with(".html") && self.minify && self.site_config.minify.html {
+            Ok(minify::minify_html(&contents, &self.site_config.minify))
+        } else {
+            Ok(contents)
+        }
+    }
+
+    // Values every template gets
+    fn add_template_values(&self, name: &str, context: &mut tera::Context) {
+        context.insert("archives", &self.archives);
 
         if name.ends_with(".html") && self.live_update {
             context.insert(
@@ -284,8 +1330,6 @@ impl Site {
                 &format!("<script>{}</script>", include_str!("./live_update.js")),
             );
         }
-
-        self.tera.render(name, &context).map_err(|e| e.into())
     }
 
     fn merge_static(&self) -> Result<()> {
@@ -303,7 +1347,7 @@ impl Site {
             }
 
             if !static_dirs.is_empty() {
-                let out = self.out_path.join("static");
+                let out = self.build_path.join("static");
 
                 for dir in static_dirs {
                     // Workaround for some copying behavior
@@ -332,12 +1376,29 @@ impl Site {
         Ok(())
     }
 
+    // Writes the files of page bundles next to their pages
+    fn copy_resources(&self) -> Result<()> {
+        for page in self.pages.values() {
+            for resource in page.resources() {
+                let out_file = self.build_path.join(resource.path.trim_start_matches('/'));
+
+                if let Some(parent) = out_file.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                fs::copy(&resource.source_path, out_file)?;
+            }
+        }
+
+        Ok(())
+    }
+
     fn copy_files(&self) -> Result<()> {
         if let Some(copy_files) = &self.site_config.site.copy_files {
             for [content_path, rel_out_path] in copy_files {
                 // A malicious site config could in theory do path traversal here, but that is not a problem currently
                 let content_path = self.root_path.join(content_path);
-                let out_path = self.out_path.join(rel_out_path);
+                let out_path = self.build_path.join(rel_out_path);
                 let files = glob(&content_path.to_string_lossy())?;
 
                 for file in files {
@@ -347,7 +1408,7 @@ impl Site {
                         &file,
                         out_path.join(
                             file.strip_prefix(
-                                &content_path.parent().ok_or_else(|| {
+                                content_path.parent().ok_or_else(|| {
                                     anyhow::anyhow!("content path missing parent")
                                 })?,
                             )?,
@@ -361,6 +1422,178 @@ impl Site {
     }
 }
 
+// Paths of the files in a dir relative to it, such as "css/style.css"
+fn relative_files(dir: &Path) -> Result<HashSet<String>> {
+    let mut files = HashSet::new();
+
+    for file in glob(&format!("{}/**/*", dir.to_string_lossy()))?
+        .filter_map(|p| p.ok())
+        .filter(|p| p.is_file())
+    {
+        files.insert(file.strip_prefix(dir)?.to_string_lossy().into());
+    }
+
+    Ok(files)
+}
+
+fn page_template(page: &Page) -> &str {
+    page.template().unwrap_or("page.html")
+}
+
+fn page_source(page: &Page) -> Option<String> {
+    page.source_path()
+        .map(|source_path| source_path.to_string_lossy().into())
+}
+
+// Swaps two dirs in one step so the out dir never goes missing while it is served
+#[cfg(target_os = "linux")]
+fn exchange_dirs(a: &Path, b: &Path) -> Result<()> {
+    use std::{ffi::CString, io, os::unix::ffi::OsStrExt};
+
+    let a_name = CString::new(a.as_os_str().as_bytes())?;
+    let b_name = CString::new(b.as_os_str().as_bytes())?;
+
+    let result = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            a_name.as_ptr(),
+            libc::AT_FDCWD,
+            b_name.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+
+    if result != 0 {
+        let err = io::Error::last_os_error();
+
+        // Some filesystems and older kernels cannot exchange, such as overlayfs, NFS or FUSE
+        return match err.raw_os_error() {
+            Some(libc::EINVAL) | Some(libc::ENOSYS) => rename_dirs(a, b),
+            _ => Err(err.into()),
+        };
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn exchange_dirs(a: &Path, b: &Path) -> Result<()> {
+    rename_dirs(a, b)
+}
+
+// Without an atomic exchange the old output is moved back when the second rename fails
+fn rename_dirs(a: &Path, b: &Path) -> Result<()> {
+    let moved_path = sibling_dir(b, "old");
+
+    if moved_path.exists() {
+        fs::remove_dir_all(&moved_path)?;
+    }
+
+    fs::rename(b, &moved_path)?;
+
+    if let Err(err) = fs::rename(a, b) {
+        fs::rename(&moved_path, b)?;
+
+        return Err(err.into());
+    }
+
+    fs::rename(&moved_path, a)?;
+
+    Ok(())
+}
+
+// Hidden dir next to the out dir, kept on the same filesystem so it can be renamed
+fn sibling_dir(out_path: &Path, suffix: &str) -> PathBuf {
+    let name = out_path.file_name().unwrap_or_default().to_string_lossy();
+
+    out_path.with_file_name(format!(".{}.{}", name, suffix))
+}
+
+// Collects template errors so every broken template is reported at once
+fn collect_template_error(errors: &mut Vec<TemplateError>, result: Result<()>) -> Result<()> {
+    match result {
+        Ok(()) => Ok(()),
+        Err(err) => match err.downcast::<TemplateError>() {
+            Ok(template_error) => {
+                errors.push(template_error);
+
+                Ok(())
+            }
+            Err(err) => Err(err),
+        },
+    }
+}
+
+#[derive(Debug)]
+pub struct TemplateError {
+    pub template: String,
+    pub template_path: Option<String>,
+    pub page: Option<PathBuf>,
+    pub messages: Vec<String>,
+}
+
+impl TemplateError {
+    fn new(template: &str, template_path: Option<String>, error: &tera::Error) -> TemplateError {
+        // Flatten the tera error chain, the innermost error is the most specific
+        let mut messages = vec![error.to_string()];
+        let mut source = error.source();
+
+        while let Some(err) = source {
+            messages.push(err.to_string());
+            source = err.source();
+        }
+
+        TemplateError {
+            template: template.into(),
+            template_path,
+            page: None,
+            messages,
+        }
+    }
+
+    fn with_page(mut self, page: &Path) -> TemplateError {
+        self.page = Some(page.into());
+        self
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error rendering template \"{}\"", self.template)?;
+
+        if let Some(page) = &self.page {
+            write!(f, " for page {:?}", page)?;
+        }
+
+        if let Some(template_path) = &self.template_path {
+            write!(f, "\n  --> {}", template_path)?;
+        }
+
+        for message in &self.messages {
+            write!(f, "\n   = {}", message)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StdError for TemplateError {}
+
+#[derive(Debug)]
+pub struct TemplateReport(pub Vec<TemplateError>);
+
+impl fmt::Display for TemplateReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} template error(s)", self.0.len())?;
+
+        for error in &self.0 {
+            write!(f, "\n\n{}", error)?;
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum SiteError {
     #[error("non absolute root path")]
@@ -371,4 +1604,321 @@ pub enum SiteError {
     Scss(String, String),
     #[error("error compiling tera template at path \"{0}\": \n{1}")]
     Tera(String, String),
+    #[error("{0}")]
+    Templates(TemplateReport),
+    #[error("page path \"{0}\" is also the path of {1:?}")]
+    PagePathCollision(String, PathBuf),
+    #[error("archive path \"{0}\" is also the path of a page")]
+    ArchiveCollision(String),
+    #[error("taxonomy path \"{0}\" is also the path of {1}")]
+    TaxonomyCollision(String, &'static str),
+    #[error("\"{0}\" is both a static file and a rendered output, rename or remove one of them")]
+    StaticCollision(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATES: &[(&str, &str)] = &[
+        (
+            "page.html",
+            "<h1>{{ page.title }}</h1>{{ document | safe }}",
+        ),
+        ("404.html", "not found"),
+        ("robots.txt", "User-agent: *"),
+        ("sitemap.xml", "<urlset></urlset>"),
+    ];
+
+    // A site with a theme at a temp dir, the files are below the root and replace the templates
+    fn test_site(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        let templates = TEMPLATES
+            .iter()
+            .map(|(name, text)| (format!("themes/test/templates/{}", name), *text));
+        let files = files.iter().map(|(path, text)| (path.to_string(), *text));
+
+        for (path, text) in templates.chain(files) {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+
+        root
+    }
+
+    fn build_site(root: &Path, config: &str) -> Result<Site> {
+        build_site_with_cache(root, config, true)
+    }
+
+    fn build_site_with_cache(root: &Path, config: &str, cache: bool) -> Result<Site> {
+        let config = SiteConfig::parse(&format!(
+            "[site]\nbase_url = \"https://example.com\"\ntheme = \"test\"\n{}",
+            config
+        ))?;
+        let mut site = Site::new(root, config, root.join("public"))?;
+        site.set_minify(false);
+        site.set_cache(cache);
+        site.load_content()?;
+        site.build()?;
+
+        Ok(site)
+    }
+
+    #[test]
+    fn static_files_collide_with_pages() {
+        let root = test_site(&[
+            ("content/static/about.org", "#+TITLE: About\n"),
+            ("static/about/index.html", "static"),
+        ]);
+
+        let err = build_site(root.path(), "").err().unwrap();
+        assert!(
+            err.to_string().contains("static/about/index.html"),
+            "{}",
+            err
+        );
+
+        fs::remove_file(root.path().join("static/about/index.html")).unwrap();
+        fs::write(root.path().join("static/style.css"), "body{}").unwrap();
+        build_site(root.path(), "").unwrap();
+
+        let public = root.path().join("public");
+        assert!(public.join("static/style.css").is_file());
+        assert_eq!(
+            fs::read_to_string(public.join("static/about/index.html")).unwrap(),
+            "<h1>About</h1>"
+        );
+    }
+
+    #[test]
+    fn copied_files_collide_with_outputs() {
+        let root = test_site(&[("files/robots.txt", "Disallow: /")]);
+
+        let err = build_site(root.path(), "copy_files = [[\"files/robots.txt\", \"\"]]")
+            .err()
+            .unwrap();
+
+        assert!(err.to_string().contains("\"robots.txt\""), "{}", err);
+    }
+
+    #[test]
+    fn posts_have_rendered_summaries() {
+        let root = test_site(&[
+            (
+                "content/posts/_index.org",
+                "#+TITLE: Posts\n#+TYPE: posts\n",
+            ),
+            (
+                "content/posts/a.org",
+                "#+TITLE: A\n#+DATE: <2021-01-01 Fri>\nThe /intro/.\n# more\nThe rest.\n",
+            ),
+            ("content/list.org", "#+TITLE: List\n#+TEMPLATE: list.html\n"),
+            (
+                "themes/test/templates/list.html",
+                "{% for post in posts.posts %}{{ post.summary | safe }}\
+                 {% if post.has_more %}more{% endif %}{% endfor %}",
+            ),
+        ]);
+
+        build_site(root.path(), "").unwrap();
+
+        assert_eq!(
+            read_output(root.path(), "list"),
+            "<p>The <i>intro</i>.</p>more"
+        );
+    }
+
+    #[test]
+    fn redirects_are_written() {
+        let root = test_site(&[("content/new.org", "#+TITLE: New\n#+ALIASES: /old\n")]);
+
+        let site = build_site(root.path(), "[redirects]\nredirects_file = true").unwrap();
+
+        let public = root.path().join("public");
+        assert!(public.join("old/index.html").is_file());
+        assert!(!public.join("redirects.map").exists());
+        assert_eq!(
+            fs::read_to_string(public.join("_redirects")).unwrap(),
+            "/old /new/ 301\n"
+        );
+        assert_eq!(site.redirects().get("/old/"), Some("/new/"));
+    }
+
+    #[test]
+    fn classed_highlighting_writes_stylesheets() {
+        let root = test_site(&[(
+            "content/a.org",
+            "#+TITLE: A\n#+BEGIN_SRC rust\nfn main() {}\n#+END_SRC\n",
+        )]);
+
+        build_site(
+            root.path(),
+            "syntax_classes = true\nsyntax_theme_dark = \"base16-ocean.dark\"",
+        )
+        .unwrap();
+
+        let public = root.path().join("public");
+        assert!(public.join("syntax-light.css").is_file());
+        assert!(public.join("syntax-dark.css").is_file());
+
+        let html = read_output(root.path(), "a");
+        assert!(
+            html.contains("<pre class=code><span class=\"source rust\">"),
+            "{}",
+            html
+        );
+        assert!(!html.contains("style="), "{}", html);
+    }
+
+    fn cached_pages(root: &Path) -> usize {
+        build_site(root, "").unwrap().report().cached_pages
+    }
+
+    fn read_output(root: &Path, page: &str) -> String {
+        fs::read_to_string(root.join("public").join(page).join("index.html")).unwrap()
+    }
+
+    #[test]
+    fn cache_renders_changed_pages_only() {
+        let root = test_site(&[
+            ("content/a.org", "#+TITLE: A\n"),
+            ("content/b.org", "#+TITLE: B\n"),
+            (
+                "content/links.org",
+                "#+TITLE: Links\n#+TEMPLATE: links.html\n",
+            ),
+            (
+                "themes/test/templates/links.html",
+                "{% set a = get_page(path=\"a\") %}{{ a.title }}",
+            ),
+        ]);
+        let root = root.path();
+
+        assert_eq!(cached_pages(root), 0);
+        assert_eq!(cached_pages(root), 3);
+        assert_eq!(read_output(root, "links"), "A");
+
+        // The links page looked up the edited page, the other page is reused
+        fs::write(root.join("content/a.org"), "#+TITLE: New A\n").unwrap();
+        assert_eq!(cached_pages(root), 1);
+        assert_eq!(read_output(root, "a"), "<h1>New A</h1>");
+        assert_eq!(read_output(root, "links"), "New A");
+        assert_eq!(read_output(root, "b"), "<h1>B</h1>");
+
+        // Only the pages rendered with the edited template
+        fs::write(
+            root.join("themes/test/templates/page.html"),
+            "{{ page.title }}",
+        )
+        .unwrap();
+        assert_eq!(cached_pages(root), 1);
+        assert_eq!(read_output(root, "b"), "B");
+    }
+
+    #[test]
+    fn cache_follows_data_and_included_files() {
+        let root = test_site(&[
+            ("content/a.org", "#+TITLE: A\n#+INCLUDE: \"part.org\"\n"),
+            ("content/part.org", "#+TITLE: Part\n"),
+            ("content/menu.org", "#+TEMPLATE: menu.html\n"),
+            ("data/menu.toml", "title = \"Menu\"\n"),
+            (
+                "themes/test/templates/menu.html",
+                "{% set menu = load_data(path=\"menu.toml\") %}{{ menu.title }}",
+            ),
+        ]);
+        let root = root.path();
+
+        assert_eq!(cached_pages(root), 0);
+        assert_eq!(cached_pages(root), 3);
+
+        fs::write(root.join("data/menu.toml"), "title = \"New menu\"\n").unwrap();
+        assert_eq!(cached_pages(root), 2);
+        assert_eq!(read_output(root, "menu"), "New menu");
+
+        // The document of the including page records the new content of the part
+        let part = root.join("content/part.org");
+        fs::write(&part, "#+TITLE: New part\n").unwrap();
+        build_site(root, "").unwrap();
+
+        let documents = root.join(cache::CACHE_DIR).join("build/documents");
+        let reads: Vec<FileRead> = fs::read_dir(documents)
+            .unwrap()
+            .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+            .flat_map(|text| {
+                serde_json::from_str::<RenderedDocument>(&text)
+                    .unwrap()
+                    .reads
+            })
+            .collect();
+
+        assert_eq!(
+            reads,
+            [FileRead {
+                hash: cache::hash_file(&part),
+                path: part,
+            }]
+        );
+    }
+
+    #[test]
+    fn no_cache_renders_every_page() {
+        let root = test_site(&[("content/a.org", "#+TITLE: A\n")]);
+
+        for _ in 0..2 {
+            let site = build_site_with_cache(root.path(), "", false).unwrap();
+
+            assert_eq!(site.report().cached_pages, 0);
+        }
+
+        assert!(!root.path().join(cache::CACHE_DIR).exists());
+        assert_eq!(read_output(root.path(), "a"), "<h1>A</h1>");
+    }
+
+    fn write_marker(dir: &Path, text: &str) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("marker"), text).unwrap();
+    }
+
+    fn read_marker(dir: &Path) -> String {
+        fs::read_to_string(dir.join("marker")).unwrap()
+    }
+
+    #[test]
+    fn exchange_dirs_swaps_contents() {
+        let temp = tempfile::tempdir().unwrap();
+        let (a, b) = (temp.path().join("a"), temp.path().join("b"));
+        write_marker(&a, "new");
+        write_marker(&b, "old");
+
+        exchange_dirs(&a, &b).unwrap();
+
+        assert_eq!(read_marker(&a), "old");
+        assert_eq!(read_marker(&b), "new");
+    }
+
+    #[test]
+    fn rename_dirs_swaps_contents() {
+        let temp = tempfile::tempdir().unwrap();
+        let (a, b) = (temp.path().join("a"), temp.path().join("b"));
+        write_marker(&a, "new");
+        write_marker(&b, "old");
+
+        rename_dirs(&a, &b).unwrap();
+
+        assert_eq!(read_marker(&a), "old");
+        assert_eq!(read_marker(&b), "new");
+        assert!(!sibling_dir(&b, "old").exists());
+    }
+
+    #[test]
+    fn rename_dirs_restores_on_failure() {
+        let temp = tempfile::tempdir().unwrap();
+        let (a, b) = (temp.path().join("missing"), temp.path().join("b"));
+        write_marker(&b, "old");
+
+        assert!(rename_dirs(&a, &b).is_err());
+        assert_eq!(read_marker(&b), "old");
+    }
 }
@@ -26,6 +26,10 @@ impl Sitemap {
 
         Ok(())
     }
+
+    pub fn get(&self, page_path: &str) -> Option<&SitemapEntry> {
+        self.pages.get(page_path)
+    }
 }
 
 #[derive(Serialize)]
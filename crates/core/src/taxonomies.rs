@@ -0,0 +1,369 @@
+use crate::{
+    archetypes::slugify,
+    config::{SiteConfig, SiteTaxonomyConfig, SlugStyle},
+    diagnostics::Diagnostics,
+    page::Page,
+    sitemap::Sitemap,
+};
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde_derive::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+use thiserror::Error;
+
+// Terms are separated by commas, or by whitespace when there is no comma
+pub fn split_terms(value: &str) -> Vec<&str> {
+    if value.contains(',') {
+        value
+            .split(',')
+            .map(|term| term.trim())
+            .filter(|term| !term.is_empty())
+            .collect()
+    } else {
+        value.split_whitespace().collect()
+    }
+}
+
+pub fn term_slug(term: &str, style: SlugStyle) -> String {
+    match style {
+        SlugStyle::Unicode => term
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<&str>>()
+            .join("-"),
+        SlugStyle::Ascii => slugify(term),
+        SlugStyle::Verbatim => term
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<&str>>()
+            .join("-"),
+    }
+}
+
+// A slug is written as a directory, so it needs a letter or digit and no characters
+// which change the meaning of a path or url, such as "..", "?" or "%"
+pub fn is_valid_slug(slug: &str) -> bool {
+    slug.chars().any(|c| c.is_alphanumeric())
+        && slug
+            .chars()
+            .all(|c| c.is_alphanumeric() || "-_.~".contains(c))
+}
+
+pub(crate) const COLLISION_HELP: &str =
+    "spell both terms the same way, or pick a slug style that keeps them apart";
+
+// Whether two terms only differ in case and word separators, such as "Web Dev" and "web-dev",
+// other terms with the same slug are reported instead of merged, such as "C" and "C++"
+pub fn same_term(a: &str, b: &str) -> bool {
+    let normalize = |term: &str| -> String {
+        term.chars()
+            .filter(|c| !c.is_whitespace() && *c != '-' && *c != '_')
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+
+    normalize(a) == normalize(b)
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TermPage {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub page_path: PathBuf,
+    pub permalink: String,
+    pub date: Option<NaiveDate>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TermLink {
+    pub name: String,
+    pub slug: String,
+    pub path: String,
+    pub permalink: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Term {
+    // Display name as first written, terms with the same slug are merged
+    pub name: String,
+    pub slug: String,
+    pub path: String,
+    pub permalink: String,
+    // Newest first, pages without a date last
+    pub pages: Vec<TermPage>,
+}
+
+impl Term {
+    // The first page is at the term path, the others below it such as "page/2/"
+    pub fn pager_path(&self, number: usize) -> String {
+        match number {
+            1 => self.path.clone(),
+            _ => format!("{}page/{}/", self.path, number),
+        }
+    }
+
+    pub fn link(&self) -> TermLink {
+        TermLink {
+            name: self.name.clone(),
+            slug: self.slug.clone(),
+            path: self.path.clone(),
+            permalink: self.permalink.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Taxonomy {
+    pub name: String,
+    pub path: String,
+    pub permalink: String,
+    // Sorted by slug
+    pub terms: Vec<Term>,
+    #[serde(skip)]
+    pub config: SiteTaxonomyConfig,
+}
+
+impl Taxonomy {
+    // Pages of a term listed on each of its pages, all of them without pagination
+    pub fn per_page(&self, term: &Term) -> usize {
+        match self.config.paginate {
+            0 => term.pages.len().max(1),
+            paginate => paginate,
+        }
+    }
+
+    pub fn term(&self, name_or_slug: &str) -> Option<&Term> {
+        let slug = term_slug(name_or_slug, self.config.slug);
+
+        self.terms.iter().find(|term| term.slug == slug)
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Taxonomies(BTreeMap<String, Taxonomy>);
+
+impl Taxonomies {
+    pub fn collect(
+        pages: &HashMap<PathBuf, Page>,
+        sitemap: &Sitemap,
+        config: &SiteConfig,
+    ) -> Result<Taxonomies> {
+        let base_url = &config.site.base_url;
+
+        let mut sorted_pages: Vec<&Page> = pages.values().collect();
+        sorted_pages.sort_by(|a, b| {
+            b.date()
+                .cmp(&a.date())
+                .then_with(|| a.page_path().cmp(b.page_path()))
+        });
+
+        let mut taxonomies = BTreeMap::new();
+        let mut diagnostics = Vec::new();
+
+        for taxonomy_config in config.taxonomies() {
+            let path = format!("/{}/", taxonomy_config.name.trim_matches('/'));
+            let mut terms: BTreeMap<String, Term> = BTreeMap::new();
+
+            for page in &sorted_pages {
+                let page_path: String = page.page_path().to_string_lossy().into();
+
+                let term_page = TermPage {
+                    title: page.title().map(|t| t.into()),
+                    description: page.description().map(|d| d.into()),
+                    page_path: page.page_path().into(),
+                    permalink: sitemap
+                        .get(&page_path)
+                        .map(|entry| entry.permalink.clone())
+                        .unwrap_or_default(),
+                    date: page.date().copied(),
+                };
+
+                for name in page.terms(&taxonomy_config.name) {
+                    let slug = term_slug(name, taxonomy_config.slug);
+                    let term_path = format!("{}{}/", path, slug);
+
+                    let term = terms.entry(slug.clone()).or_insert_with(|| Term {
+                        name: name.clone(),
+                        slug: slug.clone(),
+                        permalink: format!("{}{}", base_url, term_path),
+                        path: term_path,
+                        pages: Vec::new(),
+                    });
+
+                    if !same_term(&term.name, name) {
+                        diagnostics.push(
+                            page.keyword_diagnostic(
+                                TaxonomyError::SlugCollision(name.clone(), term.name.clone(), slug)
+                                    .to_string(),
+                                &taxonomy_config.keyword(),
+                                name,
+                            )
+                            .with_help(COLLISION_HELP),
+                        );
+
+                        continue;
+                    }
+
+                    term.pages.push(term_page.clone());
+                }
+            }
+
+            taxonomies.insert(
+                taxonomy_config.name.clone(),
+                Taxonomy {
+                    name: taxonomy_config.name.clone(),
+                    permalink: format!("{}{}", base_url, path),
+                    path,
+                    terms: terms.into_values().collect(),
+                    config: taxonomy_config,
+                },
+            );
+        }
+
+        if !diagnostics.is_empty() {
+            return Err(Diagnostics(diagnostics).into());
+        }
+
+        Ok(Taxonomies(taxonomies))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Taxonomy> {
+        self.0.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Taxonomy> {
+        self.0.values()
+    }
+
+    // The terms of a page with their paths, keyed by taxonomy
+    pub fn page_terms(&self, page: &Page) -> BTreeMap<String, Vec<TermLink>> {
+        self.0
+            .values()
+            .map(|taxonomy| {
+                let links = page
+                    .terms(&taxonomy.name)
+                    .iter()
+                    .filter_map(|name| taxonomy.term(name).map(|term| term.link()))
+                    .collect();
+
+                (taxonomy.name.clone(), links)
+            })
+            .collect()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum TaxonomyError {
+    #[error("term \"{0}\" has the same slug as \"{1}\": \"{2}\"")]
+    SlugCollision(String, String, String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_terms_by_commas_or_whitespace() {
+        assert_eq!(split_terms("Rust, web dev,, "), vec!["Rust", "web dev"]);
+        assert_eq!(split_terms("rust  web"), vec!["rust", "web"]);
+        assert!(split_terms("  ").is_empty());
+    }
+
+    #[test]
+    fn term_slug_styles() {
+        assert_eq!(term_slug("Web Dev", SlugStyle::Unicode), "web-dev");
+        assert_eq!(
+            term_slug("Café & Ünïcode", SlugStyle::Unicode),
+            "café-ünïcode"
+        );
+        assert_eq!(term_slug("Web Dev", SlugStyle::Verbatim), "Web-Dev");
+        assert_eq!(term_slug("a / b", SlugStyle::Verbatim), "a-b");
+    }
+
+    #[test]
+    fn valid_slugs() {
+        assert!(is_valid_slug("web-dev"));
+        assert!(is_valid_slug("v1.2_beta~"));
+        assert!(!is_valid_slug(""));
+        assert!(!is_valid_slug(".."));
+        assert!(!is_valid_slug("what?"));
+        assert!(!is_valid_slug("c#"));
+        assert!(!is_valid_slug("100%"));
+    }
+
+    fn config(slug: &str) -> SiteConfig {
+        SiteConfig::parse(&format!(
+            "[site]\nbase_url = \"https://example.com\"\n\n\
+             [[taxonomies]]\nname = \"tags\"\nslug = \"{}\"\n",
+            slug
+        ))
+        .unwrap()
+    }
+
+    fn parse_page(path: &str, tags: &str, config: &SiteConfig) -> Result<Page> {
+        Page::parse(
+            path.into(),
+            format!("#+TITLE: Page\n#+TAGS: {}\n\nText.\n", tags),
+            config,
+        )
+    }
+
+    #[test]
+    fn same_term_ignores_case_and_separators() {
+        assert!(same_term("Web Dev", "web-dev"));
+        assert!(same_term("web_dev", "WebDev"));
+        assert!(!same_term("C", "C++"));
+        assert!(!same_term("C#", "C"));
+    }
+
+    #[test]
+    fn page_merges_spellings_of_a_term() {
+        let config = config("ascii");
+        let page = parse_page("posts/a.org", "Web Dev, web-dev", &config).unwrap();
+
+        assert_eq!(page.terms("tags"), ["Web Dev"]);
+    }
+
+    #[test]
+    fn page_rejects_colliding_terms() {
+        let config = config("ascii");
+        let err = match parse_page("posts/a.org", "C, C++", &config) {
+            Ok(_) => panic!("the terms collide"),
+            Err(err) => err,
+        };
+
+        assert!(err
+            .to_string()
+            .contains("\"C++\" has the same slug as \"C\""));
+    }
+
+    #[test]
+    fn collect_rejects_colliding_terms_across_pages() {
+        let config = config("ascii");
+        let mut pages = HashMap::new();
+
+        for (path, tags) in [("posts/a.org", "Web Dev,"), ("posts/b.org", "web-dev")] {
+            let page = parse_page(path, tags, &config).unwrap();
+            pages.insert(page.page_path().into(), page);
+        }
+
+        let taxonomies = Taxonomies::collect(&pages, &Sitemap::default(), &config).unwrap();
+        let tags = taxonomies.get("tags").unwrap();
+
+        assert_eq!(tags.terms.len(), 1);
+        assert_eq!(tags.terms[0].pages.len(), 2);
+
+        let page = parse_page("posts/c.org", "C++", &config).unwrap();
+        pages.insert(page.page_path().into(), page);
+        let page = parse_page("posts/d.org", "C", &config).unwrap();
+        pages.insert(page.page_path().into(), page);
+
+        let err = Taxonomies::collect(&pages, &Sitemap::default(), &config).unwrap_err();
+
+        assert!(err.to_string().contains("has the same slug as"));
+    }
+}
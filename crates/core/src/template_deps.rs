@@ -0,0 +1,241 @@
+use std::collections::BTreeSet;
+use tera::{
+    ast::{Expr, ExprVal, FunctionCall, Node},
+    Tera,
+};
+
+// Variable tera sets to the whole context, as in {{ __tera_context }}
+const CONTEXT_VAR: &str = "__tera_context";
+
+// What rendering a template can depend on: the templates it extends, includes or imports
+// macros from, and the context variables any of them reads
+#[derive(Debug, Default, PartialEq)]
+pub struct TemplateDeps {
+    pub templates: BTreeSet<String>,
+    pub variables: BTreeSet<String>,
+    // Whether a template dumps the whole context, so every variable counts as read
+    pub whole_context: bool,
+}
+
+impl TemplateDeps {
+    pub fn collect(tera: &Tera, name: &str) -> TemplateDeps {
+        let mut deps = TemplateDeps::default();
+        let mut queue = vec![name.to_string()];
+
+        while let Some(name) = queue.pop() {
+            // Missing templates are reported by rendering, and missing includes may be ignored
+            let template = match tera.get_template(&name) {
+                Ok(template) => template,
+                Err(_) => continue,
+            };
+
+            if !deps.templates.insert(name) {
+                continue;
+            }
+
+            queue.extend(template.parents.iter().cloned());
+            queue.extend(
+                template
+                    .imported_macro_files
+                    .iter()
+                    .map(|(file, _)| file.clone()),
+            );
+
+            deps.visit_nodes(&template.ast, &mut queue);
+        }
+
+        deps
+    }
+
+    fn visit_nodes(&mut self, nodes: &[Node], includes: &mut Vec<String>) {
+        for node in nodes {
+            match node {
+                Node::VariableBlock(_, expr) => self.visit_expr(expr),
+                Node::MacroDefinition(_, definition, _) => {
+                    for expr in definition.args.values().flatten() {
+                        self.visit_expr(expr);
+                    }
+
+                    self.visit_nodes(&definition.body, includes);
+                }
+                Node::Include(_, names, _) => includes.extend(names.iter().cloned()),
+                Node::Set(_, set) => self.visit_expr(&set.value),
+                Node::FilterSection(_, section, _) => {
+                    self.visit_call(&section.filter);
+                    self.visit_nodes(&section.body, includes);
+                }
+                Node::Block(_, block, _) => self.visit_nodes(&block.body, includes),
+                Node::Forloop(_, forloop, _) => {
+                    self.visit_expr(&forloop.container);
+                    self.visit_nodes(&forloop.body, includes);
+
+                    if let Some(body) = &forloop.empty_body {
+                        self.visit_nodes(body, includes);
+                    }
+                }
+                Node::If(condition, _) => {
+                    for (_, expr, body) in &condition.conditions {
+                        self.visit_expr(expr);
+                        self.visit_nodes(body, includes);
+                    }
+
+                    if let Some((_, body)) = &condition.otherwise {
+                        self.visit_nodes(body, includes);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.visit_val(&expr.val);
+
+        for filter in &expr.filters {
+            self.visit_call(filter);
+        }
+    }
+
+    fn visit_call(&mut self, call: &FunctionCall) {
+        for expr in call.args.values() {
+            self.visit_expr(expr);
+        }
+    }
+
+    fn visit_val(&mut self, val: &ExprVal) {
+        match val {
+            ExprVal::Ident(ident) => self.insert_ident(ident),
+            ExprVal::Math(math) => {
+                self.visit_expr(&math.lhs);
+                self.visit_expr(&math.rhs);
+            }
+            ExprVal::Logic(logic) => {
+                self.visit_expr(&logic.lhs);
+                self.visit_expr(&logic.rhs);
+            }
+            ExprVal::Test(test) => {
+                self.insert_ident(&test.ident);
+
+                for expr in &test.args {
+                    self.visit_expr(expr);
+                }
+            }
+            ExprVal::MacroCall(call) => {
+                for expr in call.args.values() {
+                    self.visit_expr(expr);
+                }
+            }
+            ExprVal::FunctionCall(call) => self.visit_call(call),
+            ExprVal::Array(exprs) => {
+                for expr in exprs {
+                    self.visit_expr(expr);
+                }
+            }
+            ExprVal::StringConcat(concat) => {
+                for val in &concat.values {
+                    self.visit_val(val);
+                }
+            }
+            ExprVal::In(test) => {
+                self.visit_expr(&test.lhs);
+                self.visit_expr(&test.rhs);
+            }
+            _ => {}
+        }
+    }
+
+    // Identifiers such as "page.taxonomies[name]" read the variables at their roots,
+    // here page and name
+    fn insert_ident(&mut self, ident: &str) {
+        for part in ident.split(['[', ']']) {
+            let starts_variable = part
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_alphabetic() || c == '_');
+
+            if !starts_variable {
+                continue;
+            }
+
+            let variable = part.split('.').next().unwrap_or_default().trim();
+
+            if variable == CONTEXT_VAR {
+                self.whole_context = true;
+            } else {
+                self.variables.insert(variable.into());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tera(templates: &[(&str, &str)]) -> Tera {
+        let mut tera = Tera::default();
+        tera.add_raw_templates(templates.to_vec()).unwrap();
+
+        tera
+    }
+
+    fn strings(values: &[&str]) -> BTreeSet<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn collects_variables() {
+        let tera = tera(&[(
+            "page.html",
+            "{{ page.title | default(value=site_config.site.title) }}\
+             {% for post in posts.posts %}{{ post.title }}{% endfor %}\
+             {% if terms[page.ty] is defined %}{{ get_url(path=section.path) }}{% endif %}",
+        )]);
+
+        let deps = TemplateDeps::collect(&tera, "page.html");
+
+        assert_eq!(deps.templates, strings(&["page.html"]));
+        assert_eq!(
+            deps.variables,
+            strings(&["page", "post", "posts", "section", "site_config", "terms"])
+        );
+        assert!(!deps.whole_context);
+    }
+
+    #[test]
+    fn follows_parents_includes_and_macros() {
+        let tera = tera(&[
+            ("base.html", "{% block body %}{% endblock %}{{ archives }}"),
+            ("footer.html", "{{ site_config.site.title }}"),
+            (
+                "macros.html",
+                "{% macro link(page) %}{{ page.permalink }}{% endmacro %}",
+            ),
+            (
+                "page.html",
+                "{% extends \"base.html\" %}{% import \"macros.html\" as macros %}\
+                 {% block body %}{{ macros::link(page=page) }}\
+                 {% include [\"missing.html\", \"footer.html\"] ignore missing %}{% endblock %}",
+            ),
+            ("unused.html", "{{ posts }}"),
+        ]);
+
+        let deps = TemplateDeps::collect(&tera, "page.html");
+
+        assert_eq!(
+            deps.templates,
+            strings(&["base.html", "footer.html", "macros.html", "page.html"])
+        );
+        assert_eq!(
+            deps.variables,
+            strings(&["archives", "page", "site_config"])
+        );
+    }
+
+    #[test]
+    fn context_dump_reads_everything() {
+        let tera = tera(&[("debug.html", "{{ __tera_context }}")]);
+
+        assert!(TemplateDeps::collect(&tera, "debug.html").whole_context);
+    }
+}
@@ -1,48 +1,321 @@
-use crate::config::SiteConfig;
-use sha2::{Digest, Sha256};
-use std::{collections::HashMap, fs, path::PathBuf};
+use crate::{
+    assets::AssetManifest,
+    cache::{hash_bytes, hash_parts, FileReads},
+    config::{SiteConfig, SlugStyle},
+    images::{ImageProcessor, ResizeOp},
+    page::Page,
+    sections::{SectionPage, Sections},
+    taxonomies::{term_slug, Taxonomies, TermLink},
+    toc::Toc,
+};
+use chrono::NaiveDate;
+use serde_derive::Serialize;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+// Metadata of a page for listings, without the document
+#[derive(Serialize)]
+struct PageSummary<'a> {
+    title: Option<&'a str>,
+    description: Option<&'a str>,
+    ty: Option<&'a str>,
+    page_path: &'a Path,
+    permalink: &'a str,
+    date: Option<&'a NaiveDate>,
+    date_updated: Option<&'a NaiveDate>,
+    tags: &'a [String],
+    taxonomies: &'a BTreeMap<String, Vec<String>>,
+    series: Option<&'a str>,
+    series_part: Option<u32>,
+    is_section: bool,
+    weight: Option<i64>,
+}
+
+pub struct PageValue {
+    summary: tera::Value,
+    toc: tera::Value,
+    document: String,
+    // Changes with anything a template can see of the page
+    hash: String,
+}
+
+impl PageValue {
+    pub fn new(page: &Page, permalink: &str, toc: &Toc, html: &str) -> tera::Result<PageValue> {
+        let summary = PageSummary {
+            title: page.title(),
+            description: page.description(),
+            ty: page.ty(),
+            page_path: page.page_path(),
+            permalink,
+            date: page.date(),
+            date_updated: page.date_updated(),
+            tags: page.tags(),
+            taxonomies: page.taxonomies(),
+            series: page.series(),
+            series_part: page.series_part(),
+            is_section: page.is_section(),
+            weight: page.weight(),
+        };
+
+        let summary = tera::to_value(summary)?;
+        let toc = tera::to_value(toc)?;
+        let hash = hash_parts(&[&summary.to_string(), &toc.to_string(), html]);
+
+        Ok(PageValue {
+            summary,
+            toc,
+            document: html.into(),
+            hash,
+        })
+    }
+
+    // The summary with the rendered document and its table of contents
+    fn full(&self) -> tera::Value {
+        let mut value = self.summary.clone();
+
+        if let Some(object) = value.as_object_mut() {
+            object.insert(
+                "document".into(),
+                tera::Value::String(self.document.clone()),
+            );
+            object.insert("toc".into(), self.toc.clone());
+        }
+
+        value
+    }
+}
+
+// Template values of every page keyed by the page path
+pub struct PageValues {
+    values: BTreeMap<String, PageValue>,
+    // Pages looked up since the last take_reads, so cached outputs know what they depend on
+    reads: Mutex<BTreeSet<String>>,
+}
+
+impl PageValues {
+    pub fn new(values: BTreeMap<String, PageValue>) -> PageValues {
+        PageValues {
+            values,
+            reads: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    fn get(&self, path: &str) -> Option<&PageValue> {
+        let path = normalize_page_path(path);
+        self.reads.lock().unwrap().insert(path.clone());
+
+        self.values.get(&path)
+    }
+
+    // Pages in a directory without an _index.org, all of them count as read
+    fn in_directory(&self, directory: &str) -> Vec<tera::Value> {
+        let mut reads = self.reads.lock().unwrap();
+
+        self.values
+            .iter()
+            .filter(|(page_path, _)| {
+                page_path.as_str() != directory
+                    && Path::new(page_path.as_str()).parent() == Some(Path::new(directory))
+            })
+            .map(|(page_path, value)| {
+                reads.insert(page_path.clone());
+                value.summary.clone()
+            })
+            .collect()
+    }
+
+    pub fn take_reads(&self) -> Vec<String> {
+        std::mem::take(&mut *self.reads.lock().unwrap())
+            .into_iter()
+            .collect()
+    }
+
+    // Hash of a page as templates saw it, pages which no longer exist hash as empty
+    pub fn value_hash(&self, path: &str) -> &str {
+        self.values
+            .get(path)
+            .map(|value| value.hash.as_str())
+            .unwrap_or_default()
+    }
+}
+
+fn normalize_page_path(path: &str) -> String {
+    let path = path.trim_end_matches('/');
+
+    if path.is_empty() {
+        "/".into()
+    } else if path.starts_with('/') {
+        path.into()
+    } else {
+        format!("/{}", path)
+    }
+}
+
+// Listings only clone the metadata of their pages
+fn page_summaries(pages: &PageValues, paths: &[String]) -> Vec<tera::Value> {
+    paths
+        .iter()
+        .filter_map(|path| pages.get(path).map(|value| value.summary.clone()))
+        .collect()
+}
+
+fn string_arg<'a>(
+    args: &'a HashMap<String, tera::Value>,
+    function: &str,
+    name: &str,
+) -> tera::Result<Option<&'a str>> {
+    match args.get(name) {
+        Some(value) => value.as_str().map(Some).ok_or_else(|| {
+            tera::Error::msg(format!(
+                "`{}` argument `{}` must be a string, got `{}`",
+                function, name, value
+            ))
+        }),
+        None => Ok(None),
+    }
+}
+
+fn bool_arg(args: &HashMap<String, tera::Value>, function: &str, name: &str) -> tera::Result<bool> {
+    match args.get(name) {
+        Some(value) => value.as_bool().ok_or_else(|| {
+            tera::Error::msg(format!(
+                "`{}` argument `{}` must be a boolean, got `{}`",
+                function, name, value
+            ))
+        }),
+        None => Ok(false),
+    }
+}
+
+fn u32_arg(
+    args: &HashMap<String, tera::Value>,
+    function: &str,
+    name: &str,
+) -> tera::Result<Option<u32>> {
+    match args.get(name) {
+        Some(value) => value
+            .as_u64()
+            .filter(|v| *v > 0 && *v <= u32::MAX as u64)
+            .map(|v| Some(v as u32))
+            .ok_or_else(|| {
+                tera::Error::msg(format!(
+                    "`{}` argument `{}` must be a positive integer, got `{}`",
+                    function, name, value
+                ))
+            }),
+        None => Ok(None),
+    }
+}
+
+fn compare_field(a: &tera::Value, b: &tera::Value, field: &str) -> Ordering {
+    let a = a.get(field).and_then(|v| v.as_str()).unwrap_or_default();
+    let b = b.get(field).and_then(|v| v.as_str()).unwrap_or_default();
+
+    a.cmp(b)
+}
+
+// Sorts page values, dates are sorted newest first
+fn sort_pages(pages: &mut [tera::Value], sort_by: &str, reverse: bool) -> tera::Result<()> {
+    match sort_by {
+        "date" => pages.sort_by(|a, b| compare_field(b, a, "date")),
+        "title" => pages.sort_by(|a, b| compare_field(a, b, "title")),
+        "path" => pages.sort_by(|a, b| compare_field(a, b, "page_path")),
+        _ => {
+            return Err(tera::Error::msg(format!(
+                "unknown sort order \"{}\", expected date, title or path",
+                sort_by
+            )))
+        }
+    }
+
+    if reverse {
+        pages.reverse();
+    }
+
+    Ok(())
+}
 
 pub struct GetUrl {
     config: SiteConfig,
     out_path: PathBuf,
+    manifest: Arc<AssetManifest>,
+    reads: Arc<FileReads>,
 }
 impl GetUrl {
-    pub fn new(config: SiteConfig, out_path: PathBuf) -> GetUrl {
-        Self { config, out_path }
+    pub fn new(
+        config: SiteConfig,
+        out_path: PathBuf,
+        manifest: Arc<AssetManifest>,
+        reads: Arc<FileReads>,
+    ) -> GetUrl {
+        Self {
+            config,
+            out_path,
+            manifest,
+            reads,
+        }
     }
 }
 
 impl tera::Function for GetUrl {
     fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
-        let hash = args
-            .get("hash")
-            .map_or(false, |c| c == &tera::Value::Bool(true));
+        let hash = bool_arg(args, "get_url", "hash")?;
 
         let path = PathBuf::from(
-            args.get("path")
-                .expect("path argument is requried for get_url")
-                .as_str()
-                .unwrap(),
+            string_arg(args, "get_url", "path")?
+                .ok_or_else(|| tera::Error::msg("`get_url` requires a `path` argument"))?,
         );
 
         let path = path.strip_prefix("/").unwrap_or(&path);
+        let entry = self.manifest.get(&path.to_string_lossy());
 
-        let url = if hash {
-            let asset_path = self.out_path.join(path);
+        // The url changes with the content of the asset when it is fingerprinted or hashed
+        if let Some(entry) = entry.filter(|_| hash || self.config.assets.fingerprint) {
+            self.reads.insert(&self.out_path.join(path), &entry.hash);
+        }
 
-            if !asset_path.is_file() {
-                panic!(
-                    "no asset file found at {:?}, required by get_url function",
-                    asset_path
-                );
-            }
+        // Fingerprinted assets are already cache busted by their file name
+        let url = if let Some(entry) = entry.filter(|_| self.config.assets.fingerprint) {
+            format!("{}/{}", self.config.site.base_url, entry.path)
+        } else if hash {
+            // Files without an asset extension are not in the manifest
+            let hash = match entry {
+                Some(entry) => entry.hash.clone(),
+                None => {
+                    let asset_path = self.out_path.join(path);
+
+                    if !asset_path.is_file() {
+                        return Err(tera::Error::msg(format!(
+                            "`get_url` found no asset file for path \"{}\" at {:?}",
+                            path.to_string_lossy(),
+                            asset_path
+                        )));
+                    }
+
+                    let content = fs::read(&asset_path).map_err(|e| {
+                        tera::Error::chain(
+                            format!(
+                                "`get_url` could not read asset file for path \"{}\"",
+                                path.to_string_lossy()
+                            ),
+                            e,
+                        )
+                    })?;
 
-            let mut hasher = Sha256::new();
-            hasher.update(fs::read(asset_path).expect("asset file"));
-            let hash = hasher.finalize();
+                    let hash = hash_bytes(&content);
+                    self.reads.insert(&asset_path, &hash);
+
+                    hash
+                }
+            };
 
             format!(
-                "{}/{}?h={:x}",
+                "{}/{}?h={}",
                 self.config.site.base_url,
                 path.to_string_lossy(),
                 hash
@@ -58,3 +331,727 @@ impl tera::Function for GetUrl {
         Ok(tera::Value::String(url))
     }
 }
+
+pub struct GetIntegrity {
+    out_path: PathBuf,
+    manifest: Arc<AssetManifest>,
+    reads: Arc<FileReads>,
+}
+
+impl GetIntegrity {
+    pub fn new(
+        out_path: PathBuf,
+        manifest: Arc<AssetManifest>,
+        reads: Arc<FileReads>,
+    ) -> GetIntegrity {
+        Self {
+            out_path,
+            manifest,
+            reads,
+        }
+    }
+}
+
+impl tera::Function for GetIntegrity {
+    fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+        let path = string_arg(args, "get_integrity", "path")?
+            .ok_or_else(|| tera::Error::msg("`get_integrity` requires a `path` argument"))?;
+
+        let entry = self
+            .manifest
+            .get(path)
+            .filter(|entry| entry.integrity.is_some())
+            .ok_or_else(|| {
+                tera::Error::msg(format!(
+                    "`get_integrity` found no integrity hash for path \"{}\", is `assets.integrity` enabled?",
+                    path
+                ))
+            })?;
+
+        self.reads.insert(
+            &self.out_path.join(path.trim_start_matches('/')),
+            &entry.hash,
+        );
+        let integrity = entry.integrity.clone().unwrap_or_default();
+
+        Ok(tera::Value::String(integrity))
+    }
+}
+
+pub struct ResizeImage {
+    images: Arc<ImageProcessor>,
+}
+
+impl ResizeImage {
+    pub fn new(images: Arc<ImageProcessor>) -> ResizeImage {
+        Self { images }
+    }
+}
+
+impl tera::Function for ResizeImage {
+    fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+        let path = string_arg(args, "resize_image", "path")?
+            .ok_or_else(|| tera::Error::msg("`resize_image` requires a `path` argument"))?;
+        let width = u32_arg(args, "resize_image", "width")?;
+        let height = u32_arg(args, "resize_image", "height")?;
+        let format = string_arg(args, "resize_image", "format")?;
+        let op: ResizeOp = string_arg(args, "resize_image", "op")?
+            .unwrap_or("fit")
+            .parse()
+            .map_err(|e| tera::Error::chain("`resize_image` got an invalid `op`", e))?;
+
+        let processed = self
+            .images
+            .resize(path, op, width, height, format)
+            .map_err(|e| {
+                tera::Error::msg(format!(
+                    "`resize_image` could not process \"{}\": {}",
+                    path, e
+                ))
+            })?;
+
+        Ok(tera::to_value(processed)?)
+    }
+}
+
+pub struct GetPage {
+    pages: Arc<PageValues>,
+}
+
+impl GetPage {
+    pub fn new(pages: Arc<PageValues>) -> GetPage {
+        Self { pages }
+    }
+}
+
+impl tera::Function for GetPage {
+    fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+        let path = string_arg(args, "get_page", "path")?
+            .ok_or_else(|| tera::Error::msg("`get_page` requires a `path` argument"))?;
+
+        self.pages
+            .get(path)
+            .map(|value| value.full())
+            .ok_or_else(|| tera::Error::msg(format!("`get_page` found no page at \"{}\"", path)))
+    }
+}
+
+// Paths of the pages and subsections of a section, in the order of the section
+struct SectionMembers {
+    pages: Vec<String>,
+    subsections: Vec<String>,
+}
+
+pub struct GetSection {
+    pages: Arc<PageValues>,
+    // Keyed by the page path of the section
+    sections: BTreeMap<String, SectionMembers>,
+}
+
+impl GetSection {
+    pub fn new(pages: Arc<PageValues>, sections: &Sections) -> GetSection {
+        let paths = |pages: &[SectionPage]| {
+            pages
+                .iter()
+                .map(|page| page.page_path.to_string_lossy().into())
+                .collect()
+        };
+
+        let sections = sections
+            .iter()
+            .map(|section| {
+                (
+                    normalize_page_path(&section.page.page_path.to_string_lossy()),
+                    SectionMembers {
+                        pages: paths(&section.pages),
+                        subsections: paths(&section.subsections),
+                    },
+                )
+            })
+            .collect();
+
+        Self { pages, sections }
+    }
+}
+
+impl tera::Function for GetSection {
+    fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+        let path = string_arg(args, "get_section", "path")?
+            .ok_or_else(|| tera::Error::msg("`get_section` requires a `path` argument"))?;
+        let sort_by = string_arg(args, "get_section", "sort_by")?;
+        let reverse = bool_arg(args, "get_section", "reverse")?;
+
+        let section_path = normalize_page_path(path);
+
+        let (mut pages, subsections) = match self.sections.get(&section_path) {
+            Some(members) => (
+                page_summaries(&self.pages, &members.pages),
+                page_summaries(&self.pages, &members.subsections),
+            ),
+            // Pages directly inside a directory without an _index.org
+            None => (self.pages.in_directory(&section_path), Vec::new()),
+        };
+
+        match sort_by {
+            Some(sort_by) => sort_pages(&mut pages, sort_by, reverse)?,
+            None if self.sections.contains_key(&section_path) => {
+                if reverse {
+                    pages.reverse();
+                }
+            }
+            None => sort_pages(&mut pages, "date", reverse)?,
+        }
+
+        let mut section = tera::Map::new();
+        section.insert("path".into(), tera::Value::String(section_path.clone()));
+        section.insert(
+            "page".into(),
+            self.pages
+                .get(&section_path)
+                .map(|value| value.summary.clone())
+                .unwrap_or(tera::Value::Null),
+        );
+        section.insert("pages".into(), tera::Value::Array(pages));
+        section.insert("subsections".into(), tera::Value::Array(subsections));
+
+        Ok(tera::Value::Object(section))
+    }
+}
+
+pub struct GetTaxonomy {
+    pages: Arc<PageValues>,
+    // Terms of every taxonomy with the paths of their pages
+    taxonomies: BTreeMap<String, Vec<(TermLink, Vec<String>)>>,
+    slug_styles: BTreeMap<String, SlugStyle>,
+}
+
+impl GetTaxonomy {
+    pub fn new(pages: Arc<PageValues>, taxonomies: &Taxonomies) -> GetTaxonomy {
+        let mut terms = BTreeMap::new();
+        let mut slug_styles = BTreeMap::new();
+
+        for taxonomy in taxonomies.iter() {
+            let taxonomy_terms = taxonomy
+                .terms
+                .iter()
+                .map(|term| {
+                    let paths = term
+                        .pages
+                        .iter()
+                        .map(|page| page.page_path.to_string_lossy().into())
+                        .collect();
+
+                    (term.link(), paths)
+                })
+                .collect();
+
+            terms.insert(taxonomy.name.clone(), taxonomy_terms);
+            slug_styles.insert(taxonomy.name.clone(), taxonomy.config.slug);
+        }
+
+        Self {
+            pages,
+            taxonomies: terms,
+            slug_styles,
+        }
+    }
+}
+
+impl tera::Function for GetTaxonomy {
+    fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+        let kind = string_arg(args, "get_taxonomy", "kind")?.unwrap_or("tags");
+
+        let terms = self.taxonomies.get(kind).ok_or_else(|| {
+            tera::Error::msg(format!("`get_taxonomy` found no taxonomy \"{}\"", kind))
+        })?;
+
+        // A single term gives its pages, otherwise every term with its pages
+        if let Some(term) = string_arg(args, "get_taxonomy", "term")? {
+            let slug = term_slug(term, self.slug_styles[kind]);
+
+            let (_, paths) = terms
+                .iter()
+                .find(|(link, _)| link.slug == slug)
+                .ok_or_else(|| {
+                    tera::Error::msg(format!(
+                        "`get_taxonomy` found no term \"{}\" in \"{}\"",
+                        term, kind
+                    ))
+                })?;
+
+            Ok(tera::Value::Array(page_summaries(&self.pages, paths)))
+        } else {
+            let terms = terms
+                .iter()
+                .map(|(link, paths)| {
+                    let mut value = match tera::to_value(link)? {
+                        tera::Value::Object(value) => value,
+                        _ => tera::Map::new(),
+                    };
+                    value.insert(
+                        "pages".into(),
+                        tera::Value::Array(page_summaries(&self.pages, paths)),
+                    );
+
+                    Ok(tera::Value::Object(value))
+                })
+                .collect::<tera::Result<Vec<tera::Value>>>()?;
+
+            Ok(tera::Value::Array(terms))
+        }
+    }
+}
+
+// Files read by templates during a build, watched by the serve command
+pub type Dependencies = Arc<Mutex<BTreeSet<PathBuf>>>;
+
+fn parse_csv(text: &str) -> tera::Result<tera::Value> {
+    let mut reader = csv::Reader::from_reader(text.as_bytes());
+
+    let headers = reader
+        .headers()
+        .map_err(|e| tera::Error::chain("invalid csv headers", e))?
+        .clone();
+
+    let mut rows = Vec::new();
+
+    // Every row becomes an object keyed by the headers
+    for record in reader.records() {
+        let record = record.map_err(|e| tera::Error::chain("invalid csv record", e))?;
+
+        let row = headers
+            .iter()
+            .zip(record.iter())
+            .map(|(header, value)| (header.into(), tera::Value::String(value.into())))
+            .collect();
+
+        rows.push(tera::Value::Object(row));
+    }
+
+    Ok(tera::Value::Array(rows))
+}
+
+fn parse_data(text: &str, format: &str) -> tera::Result<tera::Value> {
+    match format {
+        "toml" => toml::from_str(text).map_err(|e| tera::Error::chain("invalid toml", e)),
+        "json" => serde_json::from_str(text).map_err(|e| tera::Error::chain("invalid json", e)),
+        "yaml" | "yml" => {
+            serde_yaml::from_str(text).map_err(|e| tera::Error::chain("invalid yaml", e))
+        }
+        "csv" => parse_csv(text),
+        _ => Err(tera::Error::msg(format!(
+            "unknown data format \"{}\", expected toml, json, yaml or csv",
+            format
+        ))),
+    }
+}
+
+pub struct LoadData {
+    data_path: PathBuf,
+    // Parsed files with the hash of their content
+    cache: Mutex<HashMap<PathBuf, (tera::Value, String)>>,
+    dependencies: Dependencies,
+    reads: Arc<FileReads>,
+}
+
+impl LoadData {
+    pub fn new(data_path: PathBuf, dependencies: Dependencies, reads: Arc<FileReads>) -> LoadData {
+        Self {
+            data_path,
+            cache: Mutex::new(HashMap::new()),
+            dependencies,
+            reads,
+        }
+    }
+}
+
+impl tera::Function for LoadData {
+    fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+        let path = string_arg(args, "load_data", "path")?
+            .ok_or_else(|| tera::Error::msg("`load_data` requires a `path` argument"))?;
+
+        // Avoid path traversal out of the data dir
+        let data_path = fs::canonicalize(&self.data_path)
+            .map_err(|e| tera::Error::chain("`load_data` could not find the data dir", e))?;
+        let file_path =
+            fs::canonicalize(data_path.join(path.trim_start_matches('/'))).map_err(|e| {
+                tera::Error::chain(format!("`load_data` found no file \"{}\"", path), e)
+            })?;
+
+        if !file_path.starts_with(&data_path) {
+            return Err(tera::Error::msg(format!(
+                "`load_data` path \"{}\" is outside of the data dir",
+                path
+            )));
+        }
+
+        self.dependencies.lock().unwrap().insert(file_path.clone());
+
+        if let Some((value, hash)) = self.cache.lock().unwrap().get(&file_path) {
+            self.reads.insert(&file_path, hash);
+
+            return Ok(value.clone());
+        }
+
+        let format = match string_arg(args, "load_data", "format")? {
+            Some(format) => format.to_string(),
+            None => file_path
+                .extension()
+                .map(|e| e.to_string_lossy().to_ascii_lowercase())
+                .unwrap_or_default(),
+        };
+
+        let text = fs::read_to_string(&file_path).map_err(|e| {
+            tera::Error::chain(format!("`load_data` could not read \"{}\"", path), e)
+        })?;
+
+        let value = parse_data(&text, &format).map_err(|e| {
+            tera::Error::chain(format!("`load_data` could not parse \"{}\"", path), e)
+        })?;
+
+        let hash = hash_bytes(text.as_bytes());
+        self.reads.insert(&file_path, &hash);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(file_path, (value.clone(), hash));
+
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sitemap::Sitemap;
+    use sha2::{Digest, Sha256};
+    use tera::Function;
+
+    // Page values, sections and tags of a content dir made from (path, text) pairs
+    fn read_site(files: &[(&str, &str)]) -> (Arc<PageValues>, Sections, Taxonomies) {
+        let root = tempfile::tempdir().unwrap();
+        let config = SiteConfig::parse("[site]\nbase_url = \"https://example.com\"\n").unwrap();
+        let mut pages = HashMap::new();
+
+        for (path, text) in files {
+            let path = root.path().join("content").join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, text).unwrap();
+
+            let page = Page::read_file(root.path(), path, &config, None).unwrap();
+            pages.insert(page.page_path().to_path_buf(), page);
+        }
+
+        let sitemap = Sitemap::default();
+        let sections = Sections::collect(&pages, &sitemap);
+        let taxonomies = Taxonomies::collect(&pages, &sitemap, &config).unwrap();
+        let values = pages
+            .values()
+            .map(|page| {
+                let path = page.page_path().to_string_lossy().into();
+                let html = format!("<p>{}</p>", page.title().unwrap_or_default());
+                let value = PageValue::new(page, "", &Toc::default(), &html).unwrap();
+
+                (path, value)
+            })
+            .collect();
+
+        (Arc::new(PageValues::new(values)), sections, taxonomies)
+    }
+
+    fn site() -> (Arc<PageValues>, Sections, Taxonomies) {
+        read_site(&[
+            ("docs/_index.org", "#+TITLE: Docs\n#+SORT_BY: title\n"),
+            ("docs/b.org", "#+TITLE: B\n#+TAGS: rust\n"),
+            ("docs/a.org", "#+TITLE: A\n#+TAGS: rust, web\n"),
+            ("notes/a.org", "#+TITLE: Cherry\n#+DATE: <2020-01-01 Wed>\n"),
+            ("notes/b.org", "#+TITLE: Apple\n#+DATE: <2019-01-01 Tue>\n"),
+            ("notes/c.org", "#+TITLE: Banana\n#+DATE: <2021-01-01 Fri>\n"),
+        ])
+    }
+
+    fn args(pairs: &[(&str, tera::Value)]) -> HashMap<String, tera::Value> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    fn titles(pages: &tera::Value) -> Vec<&str> {
+        pages
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|page| page["title"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn u32_arg_is_positive() {
+        let arg = |value: tera::Value| u32_arg(&args(&[("width", value)]), "resize_image", "width");
+
+        assert_eq!(arg(640.into()).unwrap(), Some(640));
+        assert!(arg(0.into()).is_err());
+        assert!(arg((-1).into()).is_err());
+        assert!(arg("640".into()).is_err());
+        assert_eq!(u32_arg(&args(&[]), "resize_image", "width").unwrap(), None);
+    }
+
+    fn get_url(manifest: AssetManifest, fingerprint: bool) -> (tempfile::TempDir, GetUrl) {
+        let out = tempfile::tempdir().unwrap();
+        let mut config = SiteConfig::parse("[site]\nbase_url = \"https://example.com\"\n").unwrap();
+        config.assets.fingerprint = fingerprint;
+        let get_url = GetUrl::new(
+            config,
+            out.path().into(),
+            Arc::new(manifest),
+            Arc::default(),
+        );
+
+        (out, get_url)
+    }
+
+    fn asset_manifest(out: &Path, fingerprint: bool, integrity: bool) -> AssetManifest {
+        fs::create_dir_all(out.join("css")).unwrap();
+        fs::write(out.join("css/style.css"), "body{}").unwrap();
+
+        let mut config = SiteConfig::parse("[site]\nbase_url = \"\"\n").unwrap();
+        config.assets.fingerprint = fingerprint;
+        config.assets.integrity = integrity;
+
+        AssetManifest::build(out, &config.assets).unwrap()
+    }
+
+    #[test]
+    fn get_url_paths() {
+        let (_out, get_url) = get_url(AssetManifest::default(), false);
+        let url = |path: &str| get_url.call(&args(&[("path", path.into())])).unwrap();
+
+        assert_eq!(url("/posts/a"), "https://example.com/posts/a/");
+        assert_eq!(url("feed.xml"), "https://example.com/feed.xml");
+    }
+
+    #[test]
+    fn get_url_argument_errors() {
+        let (_out, get_url) = get_url(AssetManifest::default(), false);
+        let error = |arguments: &[(&str, tera::Value)]| {
+            get_url.call(&args(arguments)).unwrap_err().to_string()
+        };
+
+        assert_eq!(error(&[]), "`get_url` requires a `path` argument");
+        assert_eq!(
+            error(&[("path", 1.into())]),
+            "`get_url` argument `path` must be a string, got `1`"
+        );
+        assert_eq!(
+            error(&[("path", "a.css".into()), ("hash", "yes".into())]),
+            "`get_url` argument `hash` must be a boolean, got `\"yes\"`"
+        );
+        assert!(error(&[("path", "a.css".into()), ("hash", true.into())])
+            .starts_with("`get_url` found no asset file for path \"a.css\""));
+    }
+
+    #[test]
+    fn get_url_fingerprinted() {
+        let out = tempfile::tempdir().unwrap();
+        let manifest = asset_manifest(out.path(), true, false);
+        let entry_path = manifest.get("css/style.css").unwrap().path.clone();
+        let (_out, get_url) = get_url(manifest, true);
+
+        let url = get_url
+            .call(&args(&[("path", "/css/style.css".into())]))
+            .unwrap();
+
+        assert_eq!(url, format!("https://example.com/{}", entry_path));
+        assert_eq!(get_url.reads.take().len(), 1);
+    }
+
+    #[test]
+    fn get_url_hash_from_manifest() {
+        let out = tempfile::tempdir().unwrap();
+        let manifest = asset_manifest(out.path(), false, false);
+        // The out dir of get_url is empty, so the hash can only come from the manifest
+        let (_empty_out, get_url) = get_url(manifest, false);
+
+        let url = get_url
+            .call(&args(&[
+                ("path", "css/style.css".into()),
+                ("hash", true.into()),
+            ]))
+            .unwrap();
+
+        let hash = format!("{:x}", Sha256::digest(b"body{}"));
+
+        assert_eq!(url, format!("https://example.com/css/style.css?h={}", hash));
+        assert_eq!(get_url.reads.take()[0].hash, hash);
+        assert!(get_url
+            .call(&args(&[
+                ("path", "missing.txt".into()),
+                ("hash", true.into())
+            ]))
+            .is_err());
+    }
+
+    #[test]
+    fn get_integrity_from_manifest() {
+        let out = tempfile::tempdir().unwrap();
+        let with_integrity = |integrity: bool| {
+            let manifest = asset_manifest(out.path(), false, integrity);
+
+            GetIntegrity::new(out.path().into(), Arc::new(manifest), Arc::default())
+        };
+
+        let get_integrity = with_integrity(true);
+        let integrity = get_integrity
+            .call(&args(&[("path", "/css/style.css".into())]))
+            .unwrap();
+
+        assert!(integrity.as_str().unwrap().starts_with("sha384-"));
+        assert_eq!(
+            get_integrity.reads.take()[0].path,
+            out.path().join("css/style.css")
+        );
+
+        let get_integrity = with_integrity(false);
+        assert!(get_integrity
+            .call(&args(&[("path", "css/style.css".into())]))
+            .is_err());
+    }
+
+    #[test]
+    fn get_page_with_document() {
+        let (pages, _, _) = site();
+        let get_page = GetPage::new(pages.clone());
+
+        let page = get_page.call(&args(&[("path", "docs/a/".into())])).unwrap();
+
+        assert_eq!(page["title"], "A");
+        assert_eq!(page["document"], "<p>A</p>");
+        assert!(get_page
+            .call(&args(&[("path", "/missing".into())]))
+            .is_err());
+        assert_eq!(pages.take_reads(), ["/docs/a", "/missing"]);
+    }
+
+    #[test]
+    fn get_section_in_section_order() {
+        let (pages, sections, _) = site();
+        let get_section = GetSection::new(pages, &sections);
+
+        let section = get_section
+            .call(&args(&[("path", "/docs".into())]))
+            .unwrap();
+        assert_eq!(section["page"]["title"], "Docs");
+        assert_eq!(titles(&section["pages"]), ["A", "B"]);
+
+        let section = get_section
+            .call(&args(&[("path", "/docs".into()), ("reverse", true.into())]))
+            .unwrap();
+        assert_eq!(titles(&section["pages"]), ["B", "A"]);
+    }
+
+    #[test]
+    fn get_section_of_plain_directory() {
+        let (pages, sections, _) = site();
+        let get_section = GetSection::new(pages, &sections);
+
+        let section = get_section
+            .call(&args(&[("path", "notes".into())]))
+            .unwrap();
+        assert_eq!(section["page"], tera::Value::Null);
+        assert_eq!(titles(&section["pages"]), ["Banana", "Cherry", "Apple"]);
+
+        let section = get_section
+            .call(&args(&[
+                ("path", "notes".into()),
+                ("sort_by", "title".into()),
+            ]))
+            .unwrap();
+        assert_eq!(titles(&section["pages"]), ["Apple", "Banana", "Cherry"]);
+
+        let section = get_section
+            .call(&args(&[
+                ("path", "notes".into()),
+                ("sort_by", "path".into()),
+            ]))
+            .unwrap();
+        assert_eq!(titles(&section["pages"]), ["Cherry", "Apple", "Banana"]);
+
+        assert!(get_section
+            .call(&args(&[
+                ("path", "notes".into()),
+                ("sort_by", "size".into())
+            ]))
+            .is_err());
+    }
+
+    #[test]
+    fn get_taxonomy_terms() {
+        let (pages, _, taxonomies) = site();
+        let get_taxonomy = GetTaxonomy::new(pages, &taxonomies);
+
+        let terms = get_taxonomy.call(&args(&[])).unwrap();
+        let terms = terms.as_array().unwrap();
+        assert_eq!(terms.len(), 2);
+        assert_eq!(terms[0]["slug"], "rust");
+        assert_eq!(terms[0]["pages"].as_array().unwrap().len(), 2);
+
+        let web = get_taxonomy.call(&args(&[("term", "Web".into())])).unwrap();
+        assert_eq!(titles(&web), ["A"]);
+
+        assert!(get_taxonomy.call(&args(&[("term", "go".into())])).is_err());
+        assert!(get_taxonomy
+            .call(&args(&[("kind", "categories".into())]))
+            .is_err());
+    }
+
+    #[test]
+    fn parse_data_formats() {
+        let expected = serde_json::json!({ "title": "Menu" });
+
+        assert_eq!(parse_data("title = \"Menu\"", "toml").unwrap(), expected);
+        assert_eq!(
+            parse_data("{\"title\": \"Menu\"}", "json").unwrap(),
+            expected
+        );
+        assert_eq!(parse_data("title: Menu", "yml").unwrap(), expected);
+        assert_eq!(
+            parse_data("name,url\nHome,/\nBlog,/posts\n", "csv").unwrap(),
+            serde_json::json!([
+                { "name": "Home", "url": "/" },
+                { "name": "Blog", "url": "/posts" },
+            ])
+        );
+        assert!(parse_data("title = ", "toml").is_err());
+        assert!(parse_data("title = \"Menu\"", "ini").is_err());
+    }
+
+    #[test]
+    fn load_data_records_reads() {
+        let root = tempfile::tempdir().unwrap();
+        let data_path = root.path().join("data");
+        fs::create_dir_all(&data_path).unwrap();
+        fs::write(data_path.join("menu.toml"), "title = \"Menu\"\n").unwrap();
+        fs::write(root.path().join("secret.toml"), "key = 1\n").unwrap();
+
+        let load_data = LoadData::new(data_path.clone(), Default::default(), Arc::default());
+        let load = || load_data.call(&args(&[("path", "menu.toml".into())]));
+
+        assert_eq!(load().unwrap()["title"], "Menu");
+        load_data.reads.take();
+
+        // Cached values are still recorded as read by the next output
+        assert_eq!(load().unwrap()["title"], "Menu");
+
+        let file_path = fs::canonicalize(data_path.join("menu.toml")).unwrap();
+        let reads = load_data.reads.take();
+
+        assert_eq!(reads.len(), 1);
+        assert_eq!(reads[0].path, file_path);
+        assert_eq!(reads[0].hash, crate::cache::hash_file(&file_path));
+        assert_eq!(load_data.dependencies.lock().unwrap().len(), 1);
+        assert!(load_data
+            .call(&args(&[("path", "../secret.toml".into())]))
+            .is_err());
+    }
+}
@@ -1,13 +1,13 @@
-use serde_derive::Serialize;
+use serde_derive::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashSet};
 
-#[derive(Default, Debug, Serialize)]
+#[derive(Default, Debug, Serialize, Deserialize)]
 pub struct Toc {
     sections: BTreeMap<u32, TocSection>,
     used_shortcuts: HashSet<String>,
 }
 
-#[derive(Default, Debug, Serialize)]
+#[derive(Default, Debug, Serialize, Deserialize)]
 pub struct TocSection {
     num: u32,
     shortcut: String,
@@ -59,6 +59,16 @@ pub fn create_cli() -> App<'static, 'static> {
                         .default_value("out")
                         .takes_value(true)
                         .help("Path to the output directory for the build command"),
+                )
+                .arg(
+                    Arg::with_name("no_cache")
+                        .long("no-cache")
+                        .help("Render every output without using the build cache"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the build report with every output file as json"),
                 ),
             SubCommand::with_name("serve")
                 .about("Builds the nuko site into the project dir")
@@ -81,7 +91,37 @@ pub fn create_cli() -> App<'static, 'static> {
                         .default_value("out")
                         .takes_value(true)
                         .help("Path to the output directory for the serve command"),
+                    Arg::with_name("minify")
+                        .long("minify")
+                        .help("Minify the output as configured, disabled by default when serving"),
+                    Arg::with_name("no_cache")
+                        .long("no-cache")
+                        .help("Render every output without using the build cache"),
+                ]),
+            SubCommand::with_name("clean").about("Removes the build caches of the project"),
+            SubCommand::with_name("new")
+                .about("Creates content from the archetypes of the site or theme")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommands(vec![
+                    SubCommand::with_name("post")
+                        .about("Creates a post in the posts section")
+                        .args(&[
+                            Arg::with_name("title")
+                                .required(true)
+                                .help("Title of the post, the file name is created from it"),
+                            Arg::with_name("section")
+                                .long("section")
+                                .takes_value(true)
+                                .help("Posts section relative to the content dir"),
+                        ]),
+                    SubCommand::with_name("page").about("Creates a page").arg(
+                        Arg::with_name("path")
+                            .required(true)
+                            .help("Path of the page relative to the content dir"),
+                    ),
                 ]),
+            SubCommand::with_name("syntaxes")
+                .about("Lists the available syntax highlighting languages and themes"),
         ])
 }
 
@@ -1,7 +1,13 @@
 mod build;
+mod clean;
 mod init;
+mod new;
 mod serve;
+mod syntaxes;
 
 pub use build::cmd_build;
+pub use clean::cmd_clean;
 pub use init::cmd_init;
+pub use new::{cmd_new_page, cmd_new_post};
 pub use serve::cmd_serve;
+pub use syntaxes::cmd_syntaxes;
@@ -1,9 +1,58 @@
-use crate::cli::CliConfig;
+use crate::{
+    cli::CliConfig,
+    console::{paint, print_diagnostic, Stream},
+};
 use anyhow::Result;
-use nuko_core::{config::SiteConfig, site::Site};
+use nuko_core::{config::SiteConfig, report::BuildReport, site::Site};
 use std::path::PathBuf;
 
-pub fn cmd_build(cli_config: CliConfig, out_path: PathBuf) -> Result<()> {
+fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+fn format_summary(report: &BuildReport) -> String {
+    let mut lines = vec![
+        format!(
+            "{} in {:.0}ms",
+            paint(Stream::Stdout, "Built site", "1;32"),
+            report.duration_ms
+        ),
+        format!(
+            "  pages   {} ({} cached)",
+            report.pages, report.cached_pages
+        ),
+        format!("  posts   {}", report.posts),
+        format!("  tags    {}", report.tags),
+        format!("  assets  {}", report.assets),
+        format!("  written {}", format_bytes(report.bytes_written)),
+    ];
+
+    for phase in &report.phases {
+        lines.push(format!("  {:<10} {:>8.1}ms", phase.name, phase.duration_ms));
+    }
+
+    lines.join("\n")
+}
+
+pub fn cmd_build(
+    cli_config: CliConfig,
+    out_path: PathBuf,
+    no_cache: bool,
+    json: bool,
+) -> Result<()> {
     let site_config = SiteConfig::read_file(cli_config.manifest_path())?;
 
     let mut site = Site::new(cli_config.root_path(), site_config, out_path)?;
@@ -12,9 +61,59 @@ pub fn cmd_build(cli_config: CliConfig, out_path: PathBuf) -> Result<()> {
         site.set_baseurl(base_url);
     }
 
+    site.set_cache(!no_cache);
     site.load_content()?;
 
     site.build()?;
 
+    if json {
+        println!("{}", serde_json::to_string_pretty(&*site.report())?);
+    } else {
+        for warning in &site.report().warnings {
+            print_diagnostic(warning);
+        }
+
+        println!("{}", format_summary(&site.report()));
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn format_bytes_units() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+    }
+
+    #[test]
+    fn summary_lists_counts_and_phases() {
+        let mut report = BuildReport {
+            pages: 12,
+            cached_pages: 4,
+            posts: 5,
+            tags: 3,
+            assets: 7,
+            bytes_written: 2048,
+            duration_ms: 41.6,
+            ..BuildReport::default()
+        };
+        report.add_phase("pages", Duration::from_millis(30));
+
+        assert_eq!(
+            format_summary(&report),
+            "Built site in 42ms\n\
+             \x20 pages   12 (4 cached)\n\
+             \x20 posts   5\n\
+             \x20 tags    3\n\
+             \x20 assets  7\n\
+             \x20 written 2.0 KiB\n\
+             \x20 pages          30.0ms"
+        );
+    }
+}
@@ -0,0 +1,11 @@
+use crate::cli::CliConfig;
+use anyhow::Result;
+use nuko_core::cache;
+
+pub fn cmd_clean(cli_config: CliConfig) -> Result<()> {
+    cache::clean(cli_config.root_path())?;
+
+    println!("Removed the build caches");
+
+    Ok(())
+}
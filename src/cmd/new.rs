@@ -0,0 +1,23 @@
+use crate::cli::CliConfig;
+use anyhow::Result;
+use nuko_core::{archetypes, config::SiteConfig};
+
+pub fn cmd_new_post(cli_config: CliConfig, title: &str, section: Option<&str>) -> Result<()> {
+    let site_config = SiteConfig::read_file(cli_config.manifest_path())?;
+
+    let path = archetypes::new_post(cli_config.root_path(), &site_config, title, section)?;
+
+    println!("Created {}", path.to_string_lossy());
+
+    Ok(())
+}
+
+pub fn cmd_new_page(cli_config: CliConfig, page_path: &str) -> Result<()> {
+    let site_config = SiteConfig::read_file(cli_config.manifest_path())?;
+
+    let path = archetypes::new_page(cli_config.root_path(), &site_config, page_path)?;
+
+    println!("Created {}", path.to_string_lossy());
+
+    Ok(())
+}
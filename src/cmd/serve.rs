@@ -1,4 +1,4 @@
-use crate::cli::CliConfig;
+use crate::{cli::CliConfig, console};
 use anyhow::Result;
 use bus_queue::{bounded, Publisher, Subscriber};
 use futures::{SinkExt, StreamExt};
@@ -9,20 +9,23 @@ use hyper::{
     Body, Error, Request, Response, StatusCode,
 };
 use hyper_websocket_lite::{server_upgrade, AsyncClient};
-use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
-use nuko_core::{config::SiteConfig, site::Site};
+use notify::{watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use nuko_core::{compress, config::SiteConfig, redirects::Redirects, site::Site};
 use std::{
+    collections::HashSet,
     fs,
     net::SocketAddr,
     path::{Path, PathBuf},
     sync::mpsc::channel,
-    sync::Mutex,
+    sync::{Mutex, RwLock},
     thread,
     time::Duration,
 };
 use websocket_codec::Message;
 
 lazy_static! {
+    // Aliases of the last successful build, answered with 301s
+    static ref REDIRECTS: RwLock<Redirects> = RwLock::new(Redirects::default());
     static ref UPDATE_BUS: (Mutex<Publisher<usize>>, Subscriber<usize>) = {
         let (publisher, subscriber) = bounded(1);
 
@@ -37,20 +40,165 @@ fn broadcast_update(revision: usize) -> Result<()> {
     Ok(())
 }
 
-fn build_site(cli_config: CliConfig, socket_addr: SocketAddr, out_path: PathBuf) -> Result<()> {
+// Returns the files the build depends on besides the site dirs
+fn build_site(
+    cli_config: CliConfig,
+    socket_addr: SocketAddr,
+    out_path: PathBuf,
+    minify: bool,
+    no_cache: bool,
+) -> Result<Vec<PathBuf>> {
     let site_config = SiteConfig::read_file(cli_config.manifest_path())?;
 
     let mut site = Site::new(cli_config.root_path(), site_config, out_path)?;
 
     site.set_baseurl(&format!("http://{}", &socket_addr));
     site.set_liveupdate(true);
+    site.set_minify(minify);
+    site.set_cache(!no_cache);
     site.load_content()?;
 
     site.build()?;
 
+    for warning in &site.report().warnings {
+        console::print_diagnostic(warning);
+    }
+
+    *REDIRECTS.write().unwrap() = site.redirects().clone();
+
+    Ok(site.dependencies())
+}
+
+// Files and dirs of the site which trigger a rebuild
+const WATCH_PATHS: &[&str] = &["Nuko.toml", "content", "data", "static", "themes"];
+
+// Watch the site paths which exist, optional dirs such as data may be created later
+// and removed paths are watched again once they come back
+fn watch_site_paths(
+    watcher: &mut RecommendedWatcher,
+    watched: &mut HashSet<PathBuf>,
+    root_path: &Path,
+) -> Result<()> {
+    for watch_path in WATCH_PATHS {
+        let watch_path = root_path.join(watch_path);
+
+        match watch_path.canonicalize() {
+            Ok(watch_path) if !watched.contains(&watch_path) => {
+                watcher.watch(&watch_path, RecursiveMode::Recursive)?;
+                watched.insert(watch_path);
+            }
+            Ok(_) => {}
+            Err(_) => {
+                watched.remove(&watch_path);
+            }
+        }
+    }
+
     Ok(())
 }
 
+// Watch dependencies which are not already inside a watched path,
+// both sides are canonical so symlinks and relative parts compare equal
+fn watch_dependencies(
+    watcher: &mut RecommendedWatcher,
+    watched: &mut HashSet<PathBuf>,
+    dependencies: Vec<PathBuf>,
+) -> Result<()> {
+    let mut result = Ok(());
+
+    // The other files are still watched when one cannot be, the first error is returned
+    for dependency in dependencies {
+        let dependency = dependency.canonicalize().unwrap_or(dependency);
+
+        if watched.iter().any(|path| dependency.starts_with(path)) {
+            continue;
+        }
+
+        match watcher.watch(&dependency, RecursiveMode::NonRecursive) {
+            Ok(()) => {
+                watched.insert(dependency);
+            }
+            Err(err) if result.is_ok() => result = Err(err.into()),
+            Err(_) => {}
+        }
+    }
+
+    result
+}
+
+// Changes directly in the root dir only matter for the site paths
+fn is_site_change(root_path: &Path, path: &Path) -> bool {
+    path.parent() != Some(root_path)
+        || path
+            .file_name()
+            .is_some_and(|name| WATCH_PATHS.iter().any(|watch_path| name == *watch_path))
+}
+
+// Check if the client accepts an encoding with a quality above zero,
+// a `*` entry covers the encodings which are not listed
+fn accepts_encoding(accept_encoding: &str, encoding: &str) -> bool {
+    let mut wildcard = false;
+
+    for part in accept_encoding.split(',') {
+        let mut params = part.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+
+        // A missing quality is 1, one which cannot be read is taken as 0
+        let quality = params
+            .find_map(|param| {
+                param
+                    .strip_prefix("q=")
+                    .or_else(|| param.strip_prefix("Q="))
+            })
+            .map_or(Some(1.0), |quality| quality.parse::<f32>().ok())
+            .unwrap_or(0.0);
+
+        if name.eq_ignore_ascii_case(encoding) {
+            return quality > 0.0;
+        } else if name == "*" {
+            wildcard = quality > 0.0;
+        }
+    }
+
+    wildcard
+}
+
+// Serve the precompressed sibling of the file if the client accepts it
+fn serve_file(path: &Path, accept_encoding: &str) -> Result<Response<Body>> {
+    let mime = new_mime_guess::from_path(path).first_or_text_plain();
+
+    let encoded = [("br", "br"), ("gzip", "gz")]
+        .iter()
+        .filter(|(encoding, _)| accepts_encoding(accept_encoding, encoding))
+        .map(|(encoding, extension)| (*encoding, compress::sibling_path(path, extension)))
+        .find(|(_, encoded_path)| encoded_path.is_file());
+
+    let mut res = match encoded {
+        Some((encoding, encoded_path)) => {
+            let mut res = Response::new(Body::from(fs::read(encoded_path)?));
+
+            res.headers_mut().insert(
+                header::CONTENT_ENCODING,
+                header::HeaderValue::from_static(encoding),
+            );
+
+            res
+        }
+        None => Response::new(Body::from(fs::read(path)?)),
+    };
+
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_str(mime.to_string().as_str())?,
+    );
+    res.headers_mut().insert(
+        header::VARY,
+        header::HeaderValue::from_static("accept-encoding"),
+    );
+
+    Ok(res)
+}
+
 fn render_404(out_path: &Path) -> Result<Response<Body>> {
     let _404_path = out_path.join("404.html");
 
@@ -79,16 +227,12 @@ async fn handle_ws(mut client: AsyncClient) {
     let mut subscriber = UPDATE_BUS.1.clone();
 
     // Listen for revision updates
-    loop {
-        if let Some(revision) = subscriber.next().await {
-            if client
-                .send(Message::text(revision.to_string()))
-                .await
-                .is_err()
-            {
-                break;
-            }
-        } else {
+    while let Some(revision) = subscriber.next().await {
+        if client
+            .send(Message::text(revision.to_string()))
+            .await
+            .is_err()
+        {
             break;
         }
     }
@@ -107,6 +251,18 @@ async fn handle_request(req: Request<Body>, out_path: PathBuf) -> Result<Respons
         };
     }
 
+    if let Some(target) = REDIRECTS.read().unwrap().get(&url_path) {
+        println!("{} {:?} -> {:?}", req.method().as_str(), url_path, target);
+
+        let mut res = Response::new(Body::empty());
+
+        *res.status_mut() = StatusCode::MOVED_PERMANENTLY;
+        res.headers_mut()
+            .insert(header::LOCATION, header::HeaderValue::from_str(target)?);
+
+        return Ok(res);
+    }
+
     // Avoid path traversal
     let unsafe_path = out_path.join(url_path.strip_prefix("/").unwrap_or_else(|| &url_path));
     let path = match fs::canonicalize(&unsafe_path) {
@@ -116,57 +272,57 @@ async fn handle_request(req: Request<Body>, out_path: PathBuf) -> Result<Respons
 
     println!("{} {:?}", req.method().as_str(), req.uri().to_string());
 
+    let accept_encoding = req
+        .headers()
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
     // Check if it is a path, if then look for the index.html or 404
     if path.exists() {
         if path.is_dir() {
             let index_path = path.join("index.html");
             if index_path.is_file() {
-                let content = fs::read_to_string(index_path)?;
-
-                let mut res = Response::new(Body::from(content));
-
-                res.headers_mut().insert(
-                    header::CONTENT_TYPE,
-                    header::HeaderValue::from_str("text/html")?,
-                );
-
-                Ok(res)
+                serve_file(&index_path, accept_encoding)
             } else {
                 render_404(&out_path)
             }
         // It is a file
         } else {
-            let content = fs::read(&path)?;
-            let mime = new_mime_guess::from_path(&path).first_or_text_plain();
-
-            let mut res = Response::new(Body::from(content));
-
-            res.headers_mut().insert(
-                header::CONTENT_TYPE,
-                header::HeaderValue::from_str(mime.to_string().as_str())?,
-            );
-
-            Ok(res)
+            serve_file(&path, accept_encoding)
         }
     } else {
         render_404(&out_path)
     }
 }
 
-pub fn cmd_serve(cli_config: CliConfig, socket_addr: SocketAddr, out_path: PathBuf) -> Result<()> {
+pub fn cmd_serve(
+    cli_config: CliConfig,
+    socket_addr: SocketAddr,
+    out_path: PathBuf,
+    minify: bool,
+    no_cache: bool,
+) -> Result<()> {
     let root_path = cli_config.root_path();
 
     println!("Building site...");
-    build_site(cli_config.clone(), socket_addr, out_path.clone())?;
-
-    // Setup watcher
+    let dependencies = build_site(
+        cli_config.clone(),
+        socket_addr,
+        out_path.clone(),
+        minify,
+        no_cache,
+    )?;
+
+    // Setup watcher, the root dir is watched on its own to see site dirs being created
     let (tx, rx) = channel();
-    let watch = &["Nuko.toml", "content", "static", "themes"];
+    let root_path = root_path.canonicalize()?;
     let mut watcher = watcher(tx, Duration::from_secs_f32(0.5)).unwrap();
+    let mut watched = HashSet::new();
 
-    for watch_path in watch {
-        watcher.watch(root_path.join(watch_path), RecursiveMode::Recursive)?;
-    }
+    watcher.watch(&root_path, RecursiveMode::NonRecursive)?;
+    watch_site_paths(&mut watcher, &mut watched, &root_path)?;
+    watch_dependencies(&mut watcher, &mut watched, dependencies)?;
 
     // Spawn http server
     let serve_out_path = out_path.clone();
@@ -198,14 +354,33 @@ pub fn cmd_serve(cli_config: CliConfig, socket_addr: SocketAddr, out_path: PathB
     loop {
         match rx.recv() {
             Ok(event) => match event {
-                DebouncedEvent::Create(_path)
-                | DebouncedEvent::Remove(_path)
-                | DebouncedEvent::Write(_path)
-                | DebouncedEvent::Rename(_, _path) => {
+                DebouncedEvent::Create(path)
+                | DebouncedEvent::Remove(path)
+                | DebouncedEvent::Write(path)
+                | DebouncedEvent::Rename(_, path)
+                    if is_site_change(&root_path, &path) =>
+                {
+                    if let Err(err) = watch_site_paths(&mut watcher, &mut watched, &root_path) {
+                        console::print_error(&err);
+                    }
+
                     println!("Rebuilding site...");
-                    if let Err(err) = build_site(cli_config.clone(), socket_addr, out_path.clone())
-                    {
-                        println!("Error rebuilding site: {}", err.to_string());
+                    match build_site(
+                        cli_config.clone(),
+                        socket_addr,
+                        out_path.clone(),
+                        minify,
+                        no_cache,
+                    ) {
+                        Ok(dependencies) => {
+                            // A data file which cannot be watched should not stop serving
+                            if let Err(err) =
+                                watch_dependencies(&mut watcher, &mut watched, dependencies)
+                            {
+                                console::print_error(&err);
+                            }
+                        }
+                        Err(err) => console::print_error(&err),
                     }
 
                     revision += 1;
@@ -217,3 +392,93 @@ pub fn cmd_serve(cli_config: CliConfig, socket_addr: SocketAddr, out_path: PathB
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_watcher() -> RecommendedWatcher {
+        let (tx, _rx) = channel();
+
+        watcher(tx, Duration::from_secs(1)).unwrap()
+    }
+
+    #[test]
+    fn watch_site_paths_created_later() {
+        let root = tempfile::tempdir().unwrap();
+        let root_path = root.path().canonicalize().unwrap();
+        fs::create_dir(root_path.join("content")).unwrap();
+
+        let mut watcher = test_watcher();
+        let mut watched = HashSet::new();
+
+        watch_site_paths(&mut watcher, &mut watched, &root_path).unwrap();
+        assert_eq!(watched, HashSet::from([root_path.join("content")]));
+
+        fs::create_dir(root_path.join("data")).unwrap();
+        watch_site_paths(&mut watcher, &mut watched, &root_path).unwrap();
+        assert!(watched.contains(&root_path.join("data")));
+
+        fs::remove_dir(root_path.join("data")).unwrap();
+        watch_site_paths(&mut watcher, &mut watched, &root_path).unwrap();
+        assert!(!watched.contains(&root_path.join("data")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn watch_dependencies_compares_canonical_paths() {
+        let root = tempfile::tempdir().unwrap();
+        let root_path = root.path().canonicalize().unwrap();
+        fs::create_dir_all(root_path.join("site/data")).unwrap();
+        fs::write(root_path.join("site/data/menu.toml"), "").unwrap();
+        fs::write(root_path.join("extra.toml"), "").unwrap();
+        std::os::unix::fs::symlink(root_path.join("site"), root_path.join("link")).unwrap();
+
+        let mut watcher = test_watcher();
+        let mut watched = HashSet::new();
+
+        watch_site_paths(&mut watcher, &mut watched, &root_path.join("link")).unwrap();
+        watch_dependencies(
+            &mut watcher,
+            &mut watched,
+            vec![
+                root_path.join("link/data/menu.toml"),
+                root_path.join("site/../extra.toml"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            watched,
+            HashSet::from([root_path.join("site/data"), root_path.join("extra.toml")])
+        );
+    }
+
+    #[test]
+    fn site_changes_in_root() {
+        let root = Path::new("/site");
+
+        assert!(is_site_change(root, Path::new("/site/Nuko.toml")));
+        assert!(is_site_change(root, Path::new("/site/data")));
+        assert!(is_site_change(root, Path::new("/site/content/a.org")));
+        assert!(!is_site_change(root, Path::new("/site/README.md")));
+        assert!(!is_site_change(root, Path::new("/site/public")));
+    }
+
+    #[test]
+    fn accepts_encoding_by_quality() {
+        assert!(accepts_encoding("gzip, br", "br"));
+        assert!(accepts_encoding("gzip;q=0.5, br;q=0.8", "gzip"));
+        assert!(!accepts_encoding("gzip;q=0, br", "gzip"));
+        assert!(!accepts_encoding("gzip;q=0.000", "gzip"));
+        assert!(!accepts_encoding("deflate", "gzip"));
+    }
+
+    #[test]
+    fn accepts_encoding_wildcard() {
+        assert!(accepts_encoding("*", "br"));
+        assert!(accepts_encoding("gzip, *;q=0.1", "br"));
+        assert!(!accepts_encoding("gzip, *;q=0", "br"));
+        assert!(!accepts_encoding("br;q=0, *", "br"));
+    }
+}
@@ -0,0 +1,47 @@
+use crate::cli::CliConfig;
+use anyhow::Result;
+use nuko_core::{config::SiteConfig, highlighting::Highlighting};
+
+pub fn cmd_syntaxes(cli_config: CliConfig) -> Result<()> {
+    let site_config = SiteConfig::read_file(cli_config.manifest_path())?;
+
+    let mut highlighting = Highlighting::new(cli_config.root_path(), None)?;
+    highlighting.set_aliases(site_config.syntax_aliases);
+
+    println!("Syntaxes:");
+
+    let mut syntaxes: Vec<_> = highlighting
+        .syntaxes()
+        .syntaxes()
+        .iter()
+        .filter(|syntax| !syntax.hidden)
+        .collect();
+    syntaxes.sort_by_key(|syntax| syntax.name.to_lowercase());
+
+    for syntax in syntaxes {
+        if syntax.file_extensions.is_empty() {
+            println!("  {}", syntax.name);
+        } else {
+            println!("  {} ({})", syntax.name, syntax.file_extensions.join(", "));
+        }
+    }
+
+    if !highlighting.aliases().is_empty() {
+        println!("\nAliases:");
+
+        let mut aliases: Vec<_> = highlighting.aliases().iter().collect();
+        aliases.sort();
+
+        for (alias, language) in aliases {
+            println!("  {} -> {}", alias, language);
+        }
+    }
+
+    println!("\nThemes:");
+
+    for theme in highlighting.themes().themes.keys() {
+        println!("  {}", theme);
+    }
+
+    Ok(())
+}
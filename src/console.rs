@@ -1,4 +1,5 @@
 use clap::arg_enum;
+use nuko_core::diagnostics::{Diagnostic, Diagnostics, Severity};
 use std::{
     env,
     sync::atomic::{AtomicBool, Ordering},
@@ -13,25 +14,84 @@ arg_enum! {
     }
 }
 
-static COLORS_ENABLED: AtomicBool = AtomicBool::new(false);
+// Diagnostics and errors go to stderr, so each stream is checked for a terminal on its own
+static STDOUT_COLORS_ENABLED: AtomicBool = AtomicBool::new(false);
+static STDERR_COLORS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
 
 pub fn set_color_mode(mode: ColorMode) {
-    let enabled = match mode {
+    let enabled = |stream: atty::Stream| match mode {
         ColorMode::Auto => {
-            let cli_colors = env::var("CLICOLOR").unwrap_or_else(|_| "1".into()) != "0".to_string();
-            let cli_colors_force =
-                env::var("CLICOLOR_FORCE").unwrap_or_else(|_| "0".into()) != "0".to_string();
+            let cli_colors = env::var("CLICOLOR").unwrap_or_else(|_| "1".into()) != "0";
+            let cli_colors_force = env::var("CLICOLOR_FORCE").unwrap_or_else(|_| "0".into()) != "0";
             let no_color = env::var("NO_COLOR").is_ok();
 
-            cli_colors_force || (cli_colors && !no_color && atty::is(atty::Stream::Stdout))
+            cli_colors_force || (cli_colors && !no_color && atty::is(stream))
         }
         ColorMode::Always => true,
         ColorMode::Never => false,
     };
 
-    COLORS_ENABLED.store(enabled, Ordering::Relaxed);
+    STDOUT_COLORS_ENABLED.store(enabled(atty::Stream::Stdout), Ordering::Relaxed);
+    STDERR_COLORS_ENABLED.store(enabled(atty::Stream::Stderr), Ordering::Relaxed);
+}
+
+pub fn has_color(stream: Stream) -> bool {
+    match stream {
+        Stream::Stdout => STDOUT_COLORS_ENABLED.load(Ordering::Relaxed),
+        Stream::Stderr => STDERR_COLORS_ENABLED.load(Ordering::Relaxed),
+    }
+}
+
+// Wraps the text in an ansi style, such as "1;31" for bold red, when colors are enabled
+// for the stream it is written to
+pub fn paint(stream: Stream, text: &str, style: &str) -> String {
+    if has_color(stream) {
+        format!("\x1b[{}m{}\x1b[0m", style, text)
+    } else {
+        text.into()
+    }
 }
 
-pub fn has_color() -> bool {
-    COLORS_ENABLED.load(Ordering::Relaxed)
+pub fn print_diagnostic(diagnostic: &Diagnostic) {
+    let severity = match diagnostic.severity {
+        Severity::Error => paint(Stream::Stderr, "error:", "1;31"),
+        Severity::Warning => paint(Stream::Stderr, "warning:", "1;33"),
+    };
+
+    eprintln!("{} {}\n", severity, diagnostic);
+}
+
+pub fn print_error(err: &anyhow::Error) {
+    // Diagnostics are printed one by one with their own severity
+    if let Some(diagnostics) = err.downcast_ref::<Diagnostics>() {
+        for diagnostic in &diagnostics.0 {
+            print_diagnostic(diagnostic);
+        }
+
+        let errors = diagnostics
+            .0
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+            .count();
+        eprintln!(
+            "{} could not build due to {} previous error{}",
+            paint(Stream::Stderr, "error:", "1;31"),
+            errors,
+            if errors == 1 { "" } else { "s" }
+        );
+
+        return;
+    }
+
+    eprintln!("{} {}", paint(Stream::Stderr, "error:", "1;31"), err);
+
+    for cause in err.chain().skip(1) {
+        eprintln!("  {} {}", paint(Stream::Stderr, "caused by:", "1"), cause);
+    }
 }
@@ -14,7 +14,10 @@ mod console;
 fn main() {
     match run_cli() {
         Ok(()) => (),
-        Err(err) => panic!("{:?}", err),
+        Err(err) => {
+            console::print_error(&err);
+            std::process::exit(1);
+        }
     }
 }
 
@@ -32,7 +35,10 @@ fn run_cli() -> Result<()> {
                 .root_path()
                 .join(sub_matches.value_of("out_dir").unwrap());
 
-            cmd::cmd_build(cli_config, out_path)?;
+            let no_cache = sub_matches.is_present("no_cache");
+            let json = sub_matches.is_present("json");
+
+            cmd::cmd_build(cli_config, out_path, no_cache, json)?;
         }
         ("init", Some(sub_matches)) => {
             cmd::cmd_init(sub_matches.value_of("path").unwrap());
@@ -47,7 +53,35 @@ fn run_cli() -> Result<()> {
                 .root_path()
                 .join(sub_matches.value_of("out_dir").unwrap());
 
-            cmd::cmd_serve(cli_config, socket_addr, out_path)?;
+            let minify = sub_matches.is_present("minify");
+            let no_cache = sub_matches.is_present("no_cache");
+
+            cmd::cmd_serve(cli_config, socket_addr, out_path, minify, no_cache)?;
+        }
+        ("new", Some(sub_matches)) => {
+            let cli_config = cli::create_cli_config(&matches)?;
+
+            match sub_matches.subcommand() {
+                ("post", Some(new_matches)) => cmd::cmd_new_post(
+                    cli_config,
+                    new_matches.value_of("title").unwrap(),
+                    new_matches.value_of("section"),
+                )?,
+                ("page", Some(new_matches)) => {
+                    cmd::cmd_new_page(cli_config, new_matches.value_of("path").unwrap())?
+                }
+                _ => unreachable!(),
+            }
+        }
+        ("clean", Some(_)) => {
+            let cli_config = cli::create_cli_config(&matches)?;
+
+            cmd::cmd_clean(cli_config)?;
+        }
+        ("syntaxes", Some(_)) => {
+            let cli_config = cli::create_cli_config(&matches)?;
+
+            cmd::cmd_syntaxes(cli_config)?;
         }
         _ => unreachable!(),
     }
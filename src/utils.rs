@@ -2,7 +2,7 @@ use anyhow::Result;
 use std::{fs, path::PathBuf};
 use thiserror::Error;
 
-const MANIFEST_FILE_NAME: &'static str = "Nuko.toml";
+const MANIFEST_FILE_NAME: &str = "Nuko.toml";
 const MAX_UPWARDS_MANIFEST_PEEKS: usize = 8;
 
 pub fn find_root_dir(dir: &str) -> Result<(PathBuf, PathBuf)> {
@@ -35,7 +35,7 @@ pub fn find_root_dir(dir: &str) -> Result<(PathBuf, PathBuf)> {
 
         let abs_path = std::fs::canonicalize(path)?;
 
-        return Err(UtilsError::CouldNotFindManifest(abs_path.to_string_lossy().into()).into());
+        Err(UtilsError::CouldNotFindManifest(abs_path.to_string_lossy().into()).into())
     }
 }
 